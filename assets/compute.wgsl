@@ -1,87 +1,180 @@
-
+// The previous generation, only ever read.
 @group(0) @binding(0)
-var r_cells: texture_storage_3d<r8uint, read_write>;
+var r_cells: texture_3d<u32>;
 
-fn get_cell(pos: vec3<i32>, offset_x: i32, offset_y: i32, offset_z: i32) -> i32 {
-    let value: vec4<u32> = textureLoad(r_cells, pos + vec3(offset_x, offset_y, offset_z));
-    return i32(value.x);
-}
+// The next generation, only ever written. Its format is picked by the
+// pipeline from the number of states.
+#ifdef CELLS_R8
+@group(0) @binding(2)
+var w_cells: texture_storage_3d<r8uint, write>;
+#endif
+#ifdef CELLS_R16
+@group(0) @binding(2)
+var w_cells: texture_storage_3d<r16uint, write>;
+#endif
+#ifdef CELLS_R32
+@group(0) @binding(2)
+var w_cells: texture_storage_3d<r32uint, write>;
+#endif
 
 struct Rule {
-    size: u32,
+    // Cells along x, y and z.
+    size: vec3<u32>,
     spawn_mode: u32,
     spawn_chance: f32,
-    survival: u32,
-    birth: u32,
     states: u32,
     neighbor_mode: u32,
     color_mode: u32,
+    boundary_mode: u32,
+    seed: u32,
+    radius: u32,
+    // One bit per offset of a custom neighborhood, skipping the center.
+    neighbor_mask: u32,
+    weight_mode: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
     color0: vec4<f32>,
     color1: vec4<f32>,
+    // One bit per neighbour count.
+    survival: array<vec4<u32>, 3>,
+    birth: array<vec4<u32>, 3>,
+    // Face, edge and corner weights, or one byte per offset of a custom
+    // neighborhood, depending on `weight_mode`.
+    weights: array<vec4<u32>, 2>,
 };
 
 @group(0) @binding(1)
 var<uniform> r_rule: Rule;
 
+// The largest neighbour count survival and birth hold bits for.
+let MAX_COUNT: u32 = 351u;
+
+fn resolve_axis(v: i32, size: i32) -> i32 {
+    switch i32(r_rule.boundary_mode) {
+        // Wrap
+        case 2: {
+            return ((v % size) + size) % size;
+        }
+        // Mirror
+        case 3: {
+            if v < 0 {
+                return clamp(-v - 1, 0, size - 1);
+            } else if v >= size {
+                return clamp(2 * size - v - 1, 0, size - 1);
+            }
+            return v;
+        }
+        default: {
+            return v;
+        }
+    }
+}
+
+fn get_cell(pos: vec3<i32>, offset_x: i32, offset_y: i32, offset_z: i32) -> i32 {
+    let size = vec3<i32>(r_rule.size);
+    var p = pos + vec3(offset_x, offset_y, offset_z);
+    p = vec3(resolve_axis(p.x, size.x), resolve_axis(p.y, size.y), resolve_axis(p.z, size.z));
+    if any(p < vec3(0)) || any(p >= size) {
+        switch i32(r_rule.boundary_mode) {
+            // Alive
+            case 1: {
+                return i32(r_rule.states);
+            }
+            // Dead
+            default: {
+                return 0;
+            }
+        }
+    }
+    let value: vec4<u32> = textureLoad(r_cells, p, 0);
+    return i32(value.x);
+}
+
 fn is_alive(value: i32) -> i32 {
     return value / i32(r_rule.states);
 }
 
 fn should_survive(num_neighbours: i32) -> bool {
-    return ((r_rule.survival >> u32(num_neighbours)) & u32(1)) != u32(0);
+    let n = u32(num_neighbours);
+    return n <= MAX_COUNT && ((r_rule.survival[n / 128u][(n / 32u) % 4u] >> (n % 32u)) & 1u) != 0u;
 }
 
 fn should_birth(num_neighbours: i32) -> bool {
-    return ((r_rule.birth >> u32(num_neighbours)) & u32(1)) != u32(0);
+    let n = u32(num_neighbours);
+    return n <= MAX_COUNT && ((r_rule.birth[n / 128u][(n / 32u) % 4u] >> (n % 32u)) & 1u) != 0u;
 }
 
-fn count_alive(pos: vec3<i32>) -> i32 {
+fn is_neighbor(x: i32, y: i32, z: i32) -> bool {
+    // Don't count yourself
+    if x == 0 && y == 0 && z == 0 {
+        return false;
+    }
     switch i32(r_rule.neighbor_mode) {
+        // Moore
         case 0: {
-            return is_alive(get_cell(pos, -1, -1, -1)) + 
-                   is_alive(get_cell(pos, -1, -1,  0)) + 
-                   is_alive(get_cell(pos, -1, -1,  1)) + 
-                   is_alive(get_cell(pos, -1,  0, -1)) + 
-                   is_alive(get_cell(pos, -1,  0,  0)) + 
-                   is_alive(get_cell(pos, -1,  0,  1)) + 
-                   is_alive(get_cell(pos, -1,  1, -1)) + 
-                   is_alive(get_cell(pos, -1,  1,  0)) + 
-                   is_alive(get_cell(pos, -1,  1,  1)) + 
-       
-                   is_alive(get_cell(pos,  0, -1, -1)) + 
-                   is_alive(get_cell(pos,  0, -1,  0)) + 
-                   is_alive(get_cell(pos,  0, -1,  1)) + 
-                   is_alive(get_cell(pos,  0,  0, -1)) + 
-                   //is_alive(get_cell(pos,  0,  0,  0)) + Don't count yourself
-                   is_alive(get_cell(pos,  0,  0,  1)) + 
-                   is_alive(get_cell(pos,  0,  1, -1)) + 
-                   is_alive(get_cell(pos,  0,  1,  0)) + 
-                   is_alive(get_cell(pos,  0,  1,  1)) + 
-       
-                   is_alive(get_cell(pos,  1, -1, -1)) + 
-                   is_alive(get_cell(pos,  1, -1,  0)) + 
-                   is_alive(get_cell(pos,  1, -1,  1)) + 
-                   is_alive(get_cell(pos,  1,  0, -1)) + 
-                   is_alive(get_cell(pos,  1,  0,  0)) + 
-                   is_alive(get_cell(pos,  1,  0,  1)) + 
-                   is_alive(get_cell(pos,  1,  1, -1)) + 
-                   is_alive(get_cell(pos,  1,  1,  0)) + 
-                   is_alive(get_cell(pos,  1,  1,  1));
+            return true;
         }
+        // Von Neumann
         case 1: {
-            return is_alive(get_cell(pos,  0,  0, -1)) + 
-                   is_alive(get_cell(pos,  0,  0,  1)) + 
-                   is_alive(get_cell(pos,  0, -1,  0)) + 
-                   is_alive(get_cell(pos,  0,  1,  0)) + 
-                   is_alive(get_cell(pos, -1,  0,  0)) + 
-                   is_alive(get_cell(pos,  1,  0,  0));
+            return abs(x) + abs(y) + abs(z) <= i32(r_rule.radius);
+        }
+        // Custom
+        case 2: {
+            if abs(x) > 1 || abs(y) > 1 || abs(z) > 1 {
+                return false;
+            }
+            var bit = u32((z + 1) * 9 + (y + 1) * 3 + x + 1);
+            if bit > 13u {
+                bit = bit - 1u;
+            }
+            return (r_rule.neighbor_mask & (1u << bit)) != 0u;
+        }
+        default: {
+            return false;
+        }
+    }
+}
+
+fn neighbor_weight(x: i32, y: i32, z: i32) -> i32 {
+    switch i32(r_rule.weight_mode) {
+        // Face, edge and corner
+        case 1: {
+            let axes = i32(x != 0) + i32(y != 0) + i32(z != 0);
+            return i32(r_rule.weights[0][min(axes, 3) - 1]);
+        }
+        // Per offset
+        case 2: {
+            if abs(x) > 1 || abs(y) > 1 || abs(z) > 1 {
+                return 0;
+            }
+            var i = u32((z + 1) * 9 + (y + 1) * 3 + x + 1);
+            if i > 13u {
+                i = i - 1u;
+            }
+            return i32((r_rule.weights[i / 16u][(i / 4u) % 4u] >> (i % 4u * 8u)) & 255u);
         }
         default: {
-            return 0;
+            return 1;
         }
     }
 }
 
+fn count_alive(pos: vec3<i32>) -> i32 {
+    let r = i32(r_rule.radius);
+    var count = 0;
+    for (var x = -r; x <= r; x = x + 1) {
+        for (var y = -r; y <= r; y = y + 1) {
+            for (var z = -r; z <= r; z = z + 1) {
+                if is_neighbor(x, y, z) {
+                    count = count + is_alive(get_cell(pos, x, y, z)) * neighbor_weight(x, y, z);
+                }
+            }
+        }
+    }
+    return count;
+}
+
 fn hash(value: u32) -> u32 {
     var state = value;
     state = state ^ 2747636419u;
@@ -97,22 +190,39 @@ fn random_float(value: u32) -> f32 {
     return f32(hash(value)) / 4294967295.0;
 }
 
+// The workgroup size is picked by the pipeline from the device limits.
+#ifdef WORKGROUP_9
 @compute @workgroup_size(9, 9, 9)
+#endif
+#ifdef WORKGROUP_8
+@compute @workgroup_size(8, 8, 8)
+#endif
+#ifdef WORKGROUP_6
+@compute @workgroup_size(6, 6, 6)
+#endif
+#ifdef WORKGROUP_4
+@compute @workgroup_size(4, 4, 4)
+#endif
 fn init(@builtin(global_invocation_id) pos: vec3<u32>) {
+    // The dispatch is rounded up to whole workgroups.
+    if any(pos >= r_rule.size) {
+        return;
+    }
     var alive = false;
     switch i32(r_rule.spawn_mode) {
         // Random
         case 0: {
-            let random_number = random_float(pos.z * r_rule.size * r_rule.size + pos.y * r_rule.size + pos.x);
+            let index = (pos.z * r_rule.size.y + pos.y) * r_rule.size.x + pos.x;
+            let random_number = random_float(index ^ hash(r_rule.seed));
             alive = random_number > r_rule.spawn_chance;
         }
-        // Menger Sponge
+        // Menger Sponge, with each axis divided into thirds separately
         case 1: {
             let size = r_rule.size;
             var i = u32(3);
             loop {
                 let s = size / i;
-                if size - s * i != u32(0) {
+                if any(size - s * i != vec3(u32(0))) {
                     alive = true;
                     break;
                 }
@@ -123,15 +233,31 @@ fn init(@builtin(global_invocation_id) pos: vec3<u32>) {
                 i = i * u32(3);
             }
         }
+        // Custom cells are uploaded instead, this only runs when they don't
+        // fit the grid.
         default: {}
     }
     
     
-    textureStore(r_cells, vec3<i32>(pos), vec4<u32>(u32(alive) * u32(r_rule.states)));
+    textureStore(w_cells, vec3<i32>(pos), vec4<u32>(u32(alive) * u32(r_rule.states)));
 }
 
+#ifdef WORKGROUP_9
 @compute @workgroup_size(9, 9, 9)
+#endif
+#ifdef WORKGROUP_8
+@compute @workgroup_size(8, 8, 8)
+#endif
+#ifdef WORKGROUP_6
+@compute @workgroup_size(6, 6, 6)
+#endif
+#ifdef WORKGROUP_4
+@compute @workgroup_size(4, 4, 4)
+#endif
 fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if any(invocation_id >= r_rule.size) {
+        return;
+    }
     let pos = vec3<i32>(invocation_id);
     var cur = get_cell(pos, 0, 0, 0);
 
@@ -149,9 +275,6 @@ fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         cur = cur - 1;
     }
 
-    let alive = count_alive(pos);
-
     let res = u32(cur);
-    storageBarrier();
-    textureStore(r_cells, pos, vec4<u32>(res));
+    textureStore(w_cells, pos, vec4<u32>(res));
 }
\ No newline at end of file
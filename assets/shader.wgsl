@@ -39,19 +39,48 @@ fn vertex(
 }
 
 @group(1) @binding(0)
-var r_cells: texture_storage_3d<r8uint, read_write>;
+var r_cells_a: texture_3d<u32>;
+
+@group(1) @binding(2)
+var r_cells_b: texture_3d<u32>;
+
+// Which of the two volumes holds the latest generation.
+@group(1) @binding(3)
+var<uniform> r_front: u32;
+
+fn load_cell(pos: vec3<i32>) -> u32 {
+    if r_front == 0u {
+        return textureLoad(r_cells_a, pos, 0).x;
+    } else {
+        return textureLoad(r_cells_b, pos, 0).x;
+    }
+}
 
 struct Rule {
-    size: u32,
+    // Cells along x, y and z.
+    size: vec3<u32>,
     spawn_mode: u32,
     spawn_chance: f32,
-    survival: u32,
-    birth: u32,
     states: u32,
     neighbor_mode: u32,
     color_mode: u32,
+    boundary_mode: u32,
+    seed: u32,
+    radius: u32,
+    // One bit per offset of a custom neighborhood, skipping the center.
+    neighbor_mask: u32,
+    weight_mode: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
     color0: vec4<f32>,
     color1: vec4<f32>,
+    // One bit per neighbour count.
+    survival: array<vec4<u32>, 3>,
+    birth: array<vec4<u32>, 3>,
+    // Face, edge and corner weights, or one byte per offset of a custom
+    // neighborhood, depending on `weight_mode`.
+    weights: array<vec4<u32>, 2>,
 };
 
 @group(1) @binding(1)
@@ -85,7 +114,8 @@ fn color(state: u32, p: vec3<f32>) -> vec3<f32> {
             return r_rule.color0.xyz * (1.0 - t) + r_rule.color1.xyz * t;
         }
         case 2: {
-            let t = length(p - f32(r_rule.size) / 2.0) / f32(r_rule.size);
+            let size = vec3<f32>(r_rule.size);
+            let t = length(p - size / 2.0) / max(size.x, max(size.y, size.z));
             return r_rule.color0.xyz * (1.0 - t) + r_rule.color1.xyz * t;
         }
         case 3: {
@@ -137,7 +167,7 @@ fn cast_ray(origin: vec3<f32>, dir: vec3<f32>, start_normal: vec3<f32>) -> RayHi
     var dist = 0.0;
 
     loop {
-        let state = textureLoad(r_cells, vec3<i32>(pos)).x;
+        let state = load_cell(vec3<i32>(pos));
         if state > u32(0) {
             var result: RayHit;
             result.fpos = origin + dir * dist;
@@ -153,7 +183,7 @@ fn cast_ray(origin: vec3<f32>, dir: vec3<f32>, start_normal: vec3<f32>) -> RayHi
                 pos.x = pos.x + step.x;
                 norm = vec3(step.x, 0.0, 0.0);
                 dist = tmax.x;
-                if pos.x < 0.0 || pos.x >= f32(r_rule.size) {
+                if pos.x < 0.0 || pos.x >= f32(r_rule.size.x) {
                     break;
                 }
                 tmax.x = tmax.x + delta.x;
@@ -161,7 +191,7 @@ fn cast_ray(origin: vec3<f32>, dir: vec3<f32>, start_normal: vec3<f32>) -> RayHi
                 pos.z = pos.z + step.z;
                 norm = vec3(0.0, 0.0, step.z);
                 dist = tmax.z;
-                if pos.z < 0.0 || pos.z >= f32(r_rule.size) {
+                if pos.z < 0.0 || pos.z >= f32(r_rule.size.z) {
                     break;
                 }
                 tmax.z = tmax.z + delta.z;
@@ -171,7 +201,7 @@ fn cast_ray(origin: vec3<f32>, dir: vec3<f32>, start_normal: vec3<f32>) -> RayHi
                 pos.y = pos.y + step.y;
                 norm = vec3(0.0, step.y, 0.0);
                 dist = tmax.y;
-                if pos.y < 0.0 || pos.y >= f32(r_rule.size) {
+                if pos.y < 0.0 || pos.y >= f32(r_rule.size.y) {
                     break;
                 }
                 tmax.y = tmax.y + delta.y;
@@ -179,7 +209,7 @@ fn cast_ray(origin: vec3<f32>, dir: vec3<f32>, start_normal: vec3<f32>) -> RayHi
                 pos.z = pos.z + step.z;
                 dist = tmax.z;
                 norm = vec3(0.0, 0.0, step.z);
-                if pos.z < 0.0 || pos.z >= f32(r_rule.size) {
+                if pos.z < 0.0 || pos.z >= f32(r_rule.size.z) {
                     break;
                 }
                 tmax.z = tmax.z + delta.z;
@@ -224,9 +254,11 @@ fn trace_ray(origin: vec3<f32>, dir: vec3<f32>, start_normal: vec3<f32>) -> vec3
 
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
-    let dir = normalize(in.world_position - in.cam_pos);
+    // The mesh is scaled to the grid's aspect ratio, which keeps cells cubic
+    // in world space so the direction can be used in grid space as is.
+    let dir = normalize(in.world_position - view.world_position);
     
-    let fpos = in.pos.xyz * f32(r_rule.size);
+    let fpos = in.pos.xyz * vec3<f32>(r_rule.size);
     let p = normalize((in.pos.xyz - 0.5) * 2.0);
     let res = trace_ray(fpos, dir, -in.normal);
 
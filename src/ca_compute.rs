@@ -1,31 +1,53 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    num::{NonZeroU32, NonZeroU64},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use bevy::{
     pbr::RenderMaterials,
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
         render_graph::{self, RenderGraph},
         render_resource::*,
-        renderer::{RenderContext, RenderDevice},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        settings::WgpuLimits,
+        texture::GpuImage,
         Extract, RenderApp, RenderStage,
     },
 };
 
-use crate::{rtmaterial::RTVolumeMaterial, rule::Rule, WORKGROUP_SIZE};
+use crate::{
+    history::{History, Rewind},
+    readback::Readbacks,
+    rtmaterial::RTVolumeMaterial,
+    rule::{CellFormat, GPURule, Rule, SpawnMode},
+};
 
 pub struct CAPlugin;
 
 impl Plugin for CAPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<ReInit>().init_resource::<UpdateTime>();
-        app.add_plugin(ExtractResourcePlugin::<CAImage>::default());
+        let generation = Generation::default();
+        app.init_resource::<ReInit>()
+            .init_resource::<UpdateTime>()
+            .init_resource::<Playback>()
+            .init_resource::<DoUpdate>()
+            .insert_resource(generation.clone())
+            .add_system(update_timer);
+        app.add_plugin(ExtractResourcePlugin::<CAImage>::default())
+            .add_plugin(ExtractResourcePlugin::<DoUpdate>::default());
         let render_app = app.sub_app_mut(RenderApp);
         render_app
             .init_resource::<ReInit>()
             .init_resource::<CAPipeline>()
-            .add_system_to_stage(RenderStage::Extract, extract_reinit)
-            .add_system_to_stage(RenderStage::Extract, update_timer);
+            .insert_resource(generation)
+            .add_system_to_stage(RenderStage::Extract, extract_reinit);
 
         let mut render_graph = render_app.world.get_resource_mut::<RenderGraph>().unwrap();
         render_graph.add_node("cas", DispatchCA::default());
@@ -38,8 +60,15 @@ impl Plugin for CAPlugin {
 #[derive(Default, Clone, Resource)]
 pub struct ReInit(pub bool);
 
+/// The two volumes the automaton alternates between. Each generation reads one
+/// and writes the other, so every cell sees the same previous generation.
 #[derive(Resource, Clone, ExtractResource)]
-pub struct CAImage(pub Handle<Image>);
+pub struct CAImage {
+    pub volumes: [Handle<Image>; 2],
+    /// The size the volumes were last created with. Kept here since
+    /// `GpuImage` only has their width and height.
+    pub size: UVec3,
+}
 
 fn extract_reinit(
     mut commands: Commands,
@@ -58,58 +87,225 @@ impl Default for UpdateTime {
     }
 }
 
+/// Whether the automaton advances on its own, and how many generations to
+/// step while it doesn't.
 #[derive(Resource)]
-struct DoUpdate(bool);
+pub struct Playback {
+    pub paused: bool,
+    /// Generations still to be stepped, `generations_per_frame` at a time.
+    pub steps: u32,
+    /// Generations computed in one frame, each a separate dispatch.
+    pub generations_per_frame: u32,
+    /// Ignore `UpdateTime` and pick `generations_per_frame` to keep the frame
+    /// rate around `FAST_FRAME_TIME`.
+    pub fast: bool,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            steps: 0,
+            generations_per_frame: 1,
+            fast: false,
+        }
+    }
+}
+
+/// The most generations dispatched in one frame. Many more risk the GPU
+/// timing out on large grids.
+pub const MAX_GENERATIONS_PER_FRAME: u32 = 256;
+
+/// The frame time fast mode aims for, in seconds.
+const FAST_FRAME_TIME: f32 = 1.0 / 30.0;
+
+/// The number of generations since the automaton was last initialized.
+/// Shared between the main and the render world, where `DispatchCA` counts it.
+#[derive(Resource, Clone, Default)]
+pub struct Generation(Arc<AtomicU64>);
+
+impl Generation {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How many generations to compute this frame.
+#[derive(Resource, Clone, Default, ExtractResource)]
+struct DoUpdate(u32);
 
 fn update_timer(
-    mut commands: Commands,
-    update_time: Extract<Res<UpdateTime>>,
-    input: Extract<Res<Input<KeyCode>>>,
-    time: Extract<Res<Time>>,
+    mut do_update: ResMut<DoUpdate>,
+    mut playback: ResMut<Playback>,
+    update_time: Res<UpdateTime>,
+    input: Res<Input<KeyCode>>,
+    time: Res<Time>,
     mut last_update: Local<f64>,
 ) {
     let t = time.elapsed_seconds_f64();
-    if t - *last_update > update_time.0 || input.just_pressed(KeyCode::E) {
+    if input.just_pressed(KeyCode::E) {
+        playback.steps += 1;
+    }
+    if playback.fast && !playback.paused {
+        let n = playback.generations_per_frame;
+        playback.generations_per_frame = if time.delta_seconds() < FAST_FRAME_TIME {
+            n + n / 8 + 1
+        } else {
+            n - n / 4
+        }
+        .clamp(1, MAX_GENERATIONS_PER_FRAME);
+    }
+    if playback.steps > 0 {
+        let n = playback.steps.min(playback.generations_per_frame);
+        playback.steps -= n;
+        *last_update = t;
+        do_update.0 = n;
+    } else if !playback.paused && (playback.fast || t - *last_update > update_time.0) {
         *last_update = t;
-        commands.insert_resource(DoUpdate(true));
+        do_update.0 = playback.generations_per_frame;
     } else {
-        commands.insert_resource(DoUpdate(false));
+        do_update.0 = 0;
     }
 }
 
+/// Edge lengths of the cubic workgroups `compute.wgsl` has variants for,
+/// largest first.
+const WORKGROUP_SIZES: [u32; 4] = [9, 8, 6, 4];
+
+/// The largest workgroup size the device can run.
+fn workgroup_size(limits: &WgpuLimits) -> u32 {
+    WORKGROUP_SIZES
+        .into_iter()
+        .find(|&size| {
+            size * size * size <= limits.max_compute_invocations_per_workgroup
+                && size <= limits.max_compute_workgroup_size_x
+                && size <= limits.max_compute_workgroup_size_y
+                && size <= limits.max_compute_workgroup_size_z
+        })
+        .unwrap_or(WORKGROUP_SIZES[WORKGROUP_SIZES.len() - 1])
+}
+
+/// A bind group layout and pair of pipelines for every `CellFormat`, since
+/// the format of the volume written is part of both.
 #[derive(Resource)]
 pub struct CAPipeline {
-    init_pipeline: CachedComputePipelineId,
-    update_pipeline: CachedComputePipelineId,
+    workgroup_size: u32,
+    bind_group_layouts: [BindGroupLayout; 3],
+    init_pipelines: [CachedComputePipelineId; 3],
+    update_pipelines: [CachedComputePipelineId; 3],
+}
+
+impl CAPipeline {
+    fn bind_group(
+        &self,
+        render_device: &RenderDevice,
+        format: CellFormat,
+        src: &TextureView,
+        dst: &TextureView,
+        rule: &Buffer,
+    ) -> BindGroup {
+        render_device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.bind_group_layouts[format as usize],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(src),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: rule.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(dst),
+                },
+            ],
+        })
+    }
+
+    fn ready(pipeline_cache: &PipelineCache, pipelines: &[CachedComputePipelineId]) -> bool {
+        pipelines.iter().all(|pipeline| {
+            matches!(
+                pipeline_cache.get_compute_pipeline_state(*pipeline),
+                CachedPipelineState::Ok(_)
+            )
+        })
+    }
+}
+
+fn bind_group_layout(render_device: &RenderDevice, format: CellFormat) -> BindGroupLayout {
+    render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Uint,
+                    view_dimension: TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(
+                        NonZeroU64::new(std::mem::size_of::<GPURule>() as u64).unwrap(),
+                    ),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::StorageTexture {
+                    access: StorageTextureAccess::WriteOnly,
+                    format: format.texture_format(),
+                    view_dimension: TextureViewDimension::D3,
+                },
+                count: None,
+            },
+        ],
+    })
 }
 
 impl FromWorld for CAPipeline {
     fn from_world(world: &mut World) -> Self {
-        let bind_group = RTVolumeMaterial::bind_group_layout(world.resource::<RenderDevice>());
+        let render_device = world.resource::<RenderDevice>();
+        let workgroup_size = workgroup_size(&render_device.limits());
+        let bind_group_layouts =
+            CellFormat::ALL.map(|format| bind_group_layout(render_device, format));
 
         let shader = world
             .resource::<AssetServer>()
             .load("../assets/compute.wgsl");
 
         let mut pipeline_cache = world.resource_mut::<PipelineCache>();
-        let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: Some(vec![bind_group.clone()]),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Cow::from("init"),
-        });
-        let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            label: None,
-            layout: Some(vec![bind_group.clone()]),
-            shader,
-            shader_defs: vec![],
-            entry_point: Cow::from("update"),
-        });
+        let mut queue = |format: CellFormat, entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                label: None,
+                layout: Some(vec![bind_group_layouts[format as usize].clone()]),
+                shader: shader.clone(),
+                shader_defs: vec![
+                    format.shader_def().to_string(),
+                    format!("WORKGROUP_{}", workgroup_size),
+                ],
+                entry_point: Cow::from(entry_point),
+            })
+        };
+        let init_pipelines = CellFormat::ALL.map(|format| queue(format, "init"));
+        let update_pipelines = CellFormat::ALL.map(|format| queue(format, "update"));
 
         CAPipeline {
-            init_pipeline,
-            update_pipeline,
+            workgroup_size,
+            bind_group_layouts,
+            init_pipelines,
+            update_pipelines,
         }
     }
 }
@@ -119,16 +315,29 @@ enum CAState {
     Init,
     Update,
     UpdateRun,
+    /// Copying a generation from the `History` back into the front volume.
+    Rewind(u64),
 }
 
 struct DispatchCA {
     state: CAState,
+    /// Index into `CAImage` of the volume holding the latest generation.
+    front: usize,
+    /// Generations computed by the current `UpdateRun`.
+    generations: usize,
+    /// The texture of the first volume. The volumes are recreated empty when
+    /// the grid is resized or needs another cell format, and have to be
+    /// initialized again when this changes.
+    texture: Option<TextureId>,
 }
 
 impl Default for DispatchCA {
     fn default() -> Self {
         Self {
             state: CAState::Loading,
+            front: 0,
+            generations: 0,
+            texture: None,
         }
     }
 }
@@ -141,31 +350,45 @@ impl render_graph::Node for DispatchCA {
 
         match self.state {
             CAState::Loading => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.init_pipeline)
-                {
+                if CAPipeline::ready(pipeline_cache, &pipeline.init_pipelines) {
                     self.state = CAState::Init
                 }
             }
             CAState::Init => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline)
-                {
+                if CAPipeline::ready(pipeline_cache, &pipeline.update_pipelines) {
                     self.state = CAState::Update
                 }
             }
-            CAState::Update => {
-                if do_update.0 {
-                    self.state = CAState::UpdateRun
+            CAState::Update | CAState::UpdateRun | CAState::Rewind(_) => {
+                if do_update.0 > 0 {
+                    self.state = CAState::UpdateRun;
+                    self.generations = do_update.0 as usize;
+                    self.front = (self.front + self.generations) % 2;
+                } else {
+                    self.state = CAState::Update;
                 }
             }
-            CAState::UpdateRun => {
-                self.state = CAState::Update;
+        }
+        if let Some(Rewind(Some(generation))) = world.get_resource() {
+            if matches!(self.state, CAState::Update | CAState::UpdateRun) {
+                self.state = CAState::Rewind(*generation);
             }
         }
         if let Some(ReInit(true)) = world.get_resource() {
             self.state = CAState::Init;
         }
+
+        let images = world.resource::<RenderAssets<Image>>();
+        let texture = world
+            .get_resource::<CAImage>()
+            .and_then(|ca_image| images.get(&ca_image.volumes[0]))
+            .map(|image| image.texture.id());
+        if texture != self.texture {
+            self.texture = texture;
+            if !matches!(self.state, CAState::Loading) {
+                self.state = CAState::Init;
+            }
+        }
     }
 
     fn run(
@@ -181,33 +404,183 @@ impl render_graph::Node for DispatchCA {
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<CAPipeline>();
         let rule = world.get_resource::<Rule>().unwrap();
+        let render_device = world.resource::<RenderDevice>();
+        let render_queue = world.resource::<RenderQueue>();
+        let images = world.resource::<RenderAssets<Image>>();
+        let ca_image = world.resource::<CAImage>();
+        let generation = world.resource::<Generation>();
 
-        let mut pass = render_context
-            .command_encoder
-            .begin_compute_pass(&ComputePassDescriptor::default());
+        let [a, b] = &ca_image.volumes;
+        let (Some(a), Some(b)) = (images.get(a), images.get(b)) else {
+            return Ok(());
+        };
+        // Both volumes are replaced together, skip the frame if only one has been
+        // so far, or the rule was resized before them.
+        let size = ca_image.size;
+        if a.texture_format != b.texture_format
+            || a.size != b.size
+            || a.size != size.truncate().as_vec2()
+            || size != rule.size
+        {
+            return Ok(());
+        }
+        let Some(format) = CellFormat::from_texture_format(a.texture_format) else {
+            return Ok(());
+        };
+        let volumes = [a, b];
 
+        // Per material, one bind group reading each volume and writing the other.
+        let mut bind_groups = Vec::new();
         for (_handle, prepared) in materials {
-            let wg: u32 = rule.size / WORKGROUP_SIZE;
-            pass.set_bind_group(0, &prepared.bind_group, &[]);
-            match self.state {
-                CAState::Init => {
+            if let [OwnedBindingResource::Buffer(rule_buffer), OwnedBindingResource::Buffer(front_buffer)] =
+                &prepared.bindings[..]
+            {
+                render_queue.write_buffer(
+                    front_buffer,
+                    0,
+                    bytemuck::bytes_of(&(self.front as u32)),
+                );
+                bind_groups.push([0, 1].map(|src| {
+                    pipeline.bind_group(
+                        render_device,
+                        format,
+                        &volumes[src].texture_view,
+                        &volumes[1 - src].texture_view,
+                        rule_buffer,
+                    )
+                }));
+            }
+        }
+
+        // Custom cells are written straight into the volume instead of being
+        // spawned by `init`, which only clears it if they don't fit the grid.
+        // The generation they're at, if they were.
+        let mut uploaded = None;
+        if let (CAState::Init, SpawnMode::Custom(cells)) = (&self.state, &rule.spawn_mode) {
+            if cells.grid.size() == size {
+                upload_cells(
+                    render_queue,
+                    volumes[self.front],
+                    format,
+                    size,
+                    cells.grid.cells(),
+                    rule.states,
+                );
+                uploaded = Some(cells.generation);
+            } else {
+                warn!(
+                    "Custom cells are {}, but the grid is {}",
+                    cells.grid.size(),
+                    size
+                );
+            }
+        }
+
+        if bind_groups.is_empty() {
+            return Ok(());
+        }
+        let history = world.resource::<History>();
+        let encoder = &mut render_context.command_encoder;
+
+        // Rounded up, the shaders skip invocations outside the grid.
+        let wg = (size + pipeline.workgroup_size - 1) / pipeline.workgroup_size;
+        match self.state {
+            CAState::Init => {
+                if uploaded.is_none() {
                     let init_pipeline = pipeline_cache
-                        .get_compute_pipeline(pipeline.init_pipeline)
+                        .get_compute_pipeline(pipeline.init_pipelines[format as usize])
                         .unwrap();
+                    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
                     pass.set_pipeline(init_pipeline);
-                    pass.dispatch_workgroups(wg, wg, wg);
+                    for bind_groups in &bind_groups {
+                        pass.set_bind_group(0, &bind_groups[1 - self.front], &[]);
+                        pass.dispatch_workgroups(wg.x, wg.y, wg.z);
+                    }
                 }
-                CAState::UpdateRun => {
-                    let update_pipeline = pipeline_cache
-                        .get_compute_pipeline(pipeline.update_pipeline)
-                        .unwrap();
+                generation.0.store(uploaded.unwrap_or(0), Ordering::Relaxed);
+                history.clear();
+                history.record(
+                    world,
+                    encoder,
+                    volumes[self.front],
+                    format,
+                    size,
+                    generation.get(),
+                );
+            }
+            CAState::UpdateRun => {
+                let update_pipeline = pipeline_cache
+                    .get_compute_pipeline(pipeline.update_pipelines[format as usize])
+                    .unwrap();
+                // `front` already points at the last of the generations, so
+                // the first reads the volume `generations` flips before it.
+                // Every generation gets a pass of its own, so it can be copied
+                // into the history before the next one overwrites it.
+                for i in 0..self.generations {
+                    let src = (self.front + self.generations + i) % 2;
+                    let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor::default());
                     pass.set_pipeline(update_pipeline);
-                    pass.dispatch_workgroups(wg, wg, wg);
+                    for bind_groups in &bind_groups {
+                        pass.set_bind_group(0, &bind_groups[src], &[]);
+                        pass.dispatch_workgroups(wg.x, wg.y, wg.z);
+                    }
+                    drop(pass);
+                    let current = generation.0.fetch_add(1, Ordering::Relaxed) + 1;
+                    history.record(world, encoder, volumes[1 - src], format, size, current);
+                }
+            }
+            CAState::Rewind(rewind) => {
+                if history.restore(encoder, volumes[self.front], format, size, rewind) {
+                    generation.0.store(rewind, Ordering::Relaxed);
                 }
-                _ => {}
             }
+            _ => {}
+        }
+        if !matches!(self.state, CAState::Loading) {
+            world.resource::<Readbacks>().copy(
+                world,
+                encoder,
+                volumes[self.front],
+                format,
+                size,
+                generation.get(),
+            );
         }
 
         Ok(())
     }
 }
+
+/// Writes `cells`, in texture order, into `image`, which is `size` cells large.
+fn upload_cells(
+    render_queue: &RenderQueue,
+    image: &GpuImage,
+    format: CellFormat,
+    size: UVec3,
+    cells: &[u32],
+    states: u32,
+) {
+    let mut data = Vec::with_capacity(cells.len() * format.pixel_size());
+    for &cell in cells {
+        format.encode(cell.min(states), &mut data);
+    }
+    render_queue.write_texture(
+        ImageCopyTexture {
+            texture: &image.texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        &data,
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(size.x * format.pixel_size() as u32),
+            rows_per_image: NonZeroU32::new(size.y),
+        },
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: size.z,
+        },
+    );
+}
@@ -0,0 +1,419 @@
+use bevy::prelude::UVec3;
+
+/// Headless reference implementation of the `init` and `update` entry points
+/// in `compute.wgsl`. Cells are stored in the same order as the GPU texture,
+/// `(z * size.y + y) * size.x + x`, and cells outside the grid follow
+/// `rule.boundary_mode`. The app only uses the grid to hold cells read back
+/// from the GPU, so the simulation itself is only built for tests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CpuGrid {
+    size: UVec3,
+    cells: Vec<u32>,
+}
+
+impl CpuGrid {
+    /// Wraps cells already in texture order, e.g. read back from the GPU.
+    pub fn from_cells(size: UVec3, cells: Vec<u32>) -> Self {
+        assert_eq!(
+            cells.len(),
+            size.x as usize * size.y as usize * size.z as usize
+        );
+        Self { size, cells }
+    }
+
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    pub fn cells(&self) -> &[u32] {
+        &self.cells
+    }
+}
+
+pub fn hash(value: u32) -> u32 {
+    let mut state = value;
+    state ^= 2747636419;
+    state = state.wrapping_mul(2654435769);
+    state ^= state >> 16;
+    state = state.wrapping_mul(2654435769);
+    state ^= state >> 16;
+    state = state.wrapping_mul(2654435769);
+    state
+}
+
+/// `CpuGrid::new`, `init` and `step`, see `CpuGrid`.
+#[cfg(test)]
+mod simulation {
+    use bevy::prelude::UVec3;
+
+    use super::{hash, CpuGrid};
+    use crate::rule::{BoundaryMode, Rule, SpawnMode};
+
+    impl CpuGrid {
+        /// Creates a grid for `rule` and spawns cells the same way `init` does.
+        pub fn new(rule: &Rule) -> Self {
+            let mut grid = Self {
+                size: rule.size,
+                cells: vec![0; rule.size.x as usize * rule.size.y as usize * rule.size.z as usize],
+            };
+            grid.init(rule);
+            grid
+        }
+
+        fn index(&self, x: u32, y: u32, z: u32) -> usize {
+            (z as usize * self.size.y as usize + y as usize) * self.size.x as usize + x as usize
+        }
+
+        pub fn get(&self, x: u32, y: u32, z: u32) -> u32 {
+            self.cells[self.index(x, y, z)]
+        }
+
+        pub fn set(&mut self, x: u32, y: u32, z: u32, state: u32) {
+            let index = self.index(x, y, z);
+            self.cells[index] = state;
+        }
+
+        fn get_offset(&self, pos: [u32; 3], offset: [i32; 3], rule: &Rule) -> u32 {
+            let mut p = [0; 3];
+            for i in 0..3 {
+                let v = pos[i] as i64 + offset[i] as i64;
+                match rule.boundary_mode.resolve(v, self.size[i] as i64) {
+                    Some(v) => p[i] = v as u32,
+                    None if rule.boundary_mode == BoundaryMode::Alive => return rule.states,
+                    None => return 0,
+                }
+            }
+            self.get(p[0], p[1], p[2])
+        }
+
+        pub fn alive_count(&self, rule: &Rule) -> usize {
+            self.cells.iter().filter(|&&c| is_alive(c, rule)).count()
+        }
+
+        /// Re-spawns every cell according to `rule.spawn_mode`.
+        pub fn init(&mut self, rule: &Rule) {
+            if let SpawnMode::Custom(cells) = &rule.spawn_mode {
+                if cells.grid.size() == self.size {
+                    for (cell, &state) in self.cells.iter_mut().zip(cells.grid.cells()) {
+                        *cell = state.min(rule.states);
+                    }
+                } else {
+                    self.cells.fill(0);
+                }
+                return;
+            }
+            let size = self.size;
+            for z in 0..size.z {
+                for y in 0..size.y {
+                    for x in 0..size.x {
+                        let alive = match rule.spawn_mode {
+                            SpawnMode::Random(chance, seed) => {
+                                let index = (z * size.y + y) * size.x + x;
+                                random_float(index ^ hash(seed)) > chance
+                            }
+                            SpawnMode::MengerSponge => menger_alive(size, [x, y, z]),
+                            SpawnMode::Custom(_) => unreachable!(),
+                        };
+                        self.set(x, y, z, alive as u32 * rule.states);
+                    }
+                }
+            }
+        }
+
+        /// Advances the grid by one generation.
+        pub fn step(&mut self, rule: &Rule) {
+            let mut next = self.cells.clone();
+            let size = self.size;
+            for z in 0..size.z {
+                for y in 0..size.y {
+                    for x in 0..size.x {
+                        let pos = [x, y, z];
+                        let cur = self.get(x, y, z);
+                        let alive = self.count_alive(pos, rule);
+
+                        let res = if is_alive(cur, rule) {
+                            if !rule.survival.get(alive) {
+                                cur - 1
+                            } else {
+                                cur
+                            }
+                        } else if cur == 0 {
+                            if rule.birth.get(alive) {
+                                rule.states
+                            } else {
+                                0
+                            }
+                        } else {
+                            cur - 1
+                        };
+                        next[self.index(x, y, z)] = res;
+                    }
+                }
+            }
+            self.cells = next;
+        }
+
+        fn count_alive(&self, pos: [u32; 3], rule: &Rule) -> usize {
+            let r = rule.radius as i32;
+            let mut count = 0;
+            for x in -r..=r {
+                for y in -r..=r {
+                    for z in -r..=r {
+                        let offset = [x, y, z];
+                        if rule.neighbor_mode.contains(offset, rule.radius)
+                            && is_alive(self.get_offset(pos, offset, rule), rule)
+                        {
+                            count += rule.weights.weight(offset) as usize;
+                        }
+                    }
+                }
+            }
+            count
+        }
+    }
+
+    impl BoundaryMode {
+        /// Maps a coordinate along an axis of length `size` back into the grid,
+        /// or returns `None` if the cell is outside and has a fixed state.
+        pub fn resolve(&self, v: i64, size: i64) -> Option<i64> {
+            if (0..size).contains(&v) {
+                return Some(v);
+            }
+            match self {
+                Self::Dead | Self::Alive => None,
+                Self::Wrap => Some(v.rem_euclid(size)),
+                Self::Mirror => {
+                    let v = if v < 0 { -v - 1 } else { 2 * size - v - 1 };
+                    Some(v.clamp(0, size - 1))
+                }
+            }
+        }
+    }
+
+    fn is_alive(state: u32, rule: &Rule) -> bool {
+        state / rule.states == 1
+    }
+
+    /// Each axis is divided into thirds separately, so grids that aren't cubes
+    /// get a stretched sponge.
+    fn menger_alive(size: UVec3, pos: [u32; 3]) -> bool {
+        let mut i = 3;
+        loop {
+            let s = size / i;
+            if size - s * i != UVec3::ZERO {
+                return true;
+            }
+            let p: i32 = pos
+                .iter()
+                .zip(s.to_array())
+                .map(|(&v, s)| ((v / s) % 3) as i32 - 1)
+                .map(i32::abs)
+                .sum();
+            if p <= 1 {
+                return false;
+            }
+            i *= 3;
+        }
+    }
+
+    fn random_float(value: u32) -> f32 {
+        hash(value) as f32 / 4294967295.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{BoundaryMode, NeighborMode, Rule, SpawnMode};
+
+    fn rule(survival: &[usize], birth: &[usize], states: u32, neighbor_mode: NeighborMode) -> Rule {
+        Rule {
+            size: UVec3::splat(6),
+            // Never spawns anything, cells are set by the tests.
+            spawn_mode: SpawnMode::Random(1.0, 0),
+            survival: survival.to_vec().into(),
+            birth: birth.to_vec().into(),
+            states,
+            neighbor_mode,
+            ..Rule::default()
+        }
+    }
+
+    /// A grid for `rule` with `cells` set to `state` and every other cell dead.
+    fn grid_with(rule: &Rule, cells: &[[u32; 3]], state: u32) -> CpuGrid {
+        let mut grid = CpuGrid::new(rule);
+        for &[x, y, z] in cells {
+            grid.set(x, y, z, state);
+        }
+        grid
+    }
+
+    fn cube(at: u32) -> Vec<[u32; 3]> {
+        let mut cells = Vec::new();
+        for z in at..at + 2 {
+            for y in at..at + 2 {
+                for x in at..at + 2 {
+                    cells.push([x, y, z]);
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn cube_is_still_life_under_moore() {
+        // Every cell of the cube has 7 neighbours, cells outside at most 4.
+        let rule = rule(&[7], &[5, 6], 1, NeighborMode::Moore);
+        let mut grid = grid_with(&rule, &cube(2), 1);
+        let start = grid.clone();
+        for _ in 0..3 {
+            grid.step(&rule);
+            assert_eq!(grid, start);
+        }
+    }
+
+    #[test]
+    fn cube_is_still_life_under_von_neumann_only() {
+        // Every cell of the cube shares a face with 3 others, cells outside with 1.
+        let von_neumann = rule(&[3], &[2], 1, NeighborMode::VonNeumann);
+        let mut grid = grid_with(&von_neumann, &cube(2), 1);
+        let start = grid.clone();
+        grid.step(&von_neumann);
+        assert_eq!(grid, start);
+
+        // With corners and edges the cube is too crowded to survive.
+        let moore = rule(&[3], &[], 1, NeighborMode::Moore);
+        grid.step(&moore);
+        assert_eq!(grid.alive_count(&moore), 0);
+    }
+
+    #[test]
+    fn blinker_oscillates_in_a_flat_grid() {
+        // A single layer with dead faces is Conway's Life, B3/S2,3.
+        let rule = Rule {
+            size: UVec3::new(5, 5, 1),
+            ..rule(&[2, 3], &[3], 1, NeighborMode::Moore)
+        };
+        let horizontal = grid_with(&rule, &[[1, 2, 0], [2, 2, 0], [3, 2, 0]], 1);
+        let vertical = grid_with(&rule, &[[2, 1, 0], [2, 2, 0], [2, 3, 0]], 1);
+        let mut grid = horizontal.clone();
+        grid.step(&rule);
+        assert_eq!(grid, vertical);
+        grid.step(&rule);
+        assert_eq!(grid, horizontal);
+    }
+
+    #[test]
+    fn wrap_reaches_across_faces_and_dead_does_not() {
+        let dead = Rule {
+            size: UVec3::new(4, 1, 1),
+            ..rule(&[], &[1], 1, NeighborMode::VonNeumann)
+        };
+        let mut grid = grid_with(&dead, &[[0, 0, 0]], 1);
+        grid.step(&dead);
+        assert_eq!(grid.cells(), [0, 1, 0, 0]);
+
+        let wrap = Rule {
+            boundary_mode: BoundaryMode::Wrap,
+            ..dead
+        };
+        let mut grid = grid_with(&wrap, &[[0, 0, 0]], 1);
+        grid.step(&wrap);
+        // The last cell is next to the first across the face.
+        assert_eq!(grid.cells(), [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn alive_boundary_counts_as_neighbours() {
+        let rule = Rule {
+            size: UVec3::new(3, 1, 1),
+            boundary_mode: BoundaryMode::Alive,
+            ..rule(&[], &[5], 1, NeighborMode::VonNeumann)
+        };
+        let mut grid = CpuGrid::new(&rule);
+        grid.step(&rule);
+        // Every cell has 4 neighbours outside along y and z, the ends a 5th along x.
+        assert_eq!(grid.cells(), [1, 0, 1]);
+    }
+
+    #[test]
+    fn boundaries_resolve_coordinates_outside_the_grid() {
+        assert_eq!(BoundaryMode::Dead.resolve(-1, 4), None);
+        assert_eq!(BoundaryMode::Alive.resolve(4, 4), None);
+        assert_eq!(BoundaryMode::Wrap.resolve(-1, 4), Some(3));
+        assert_eq!(BoundaryMode::Wrap.resolve(5, 4), Some(1));
+        assert_eq!(BoundaryMode::Mirror.resolve(-1, 4), Some(0));
+        assert_eq!(BoundaryMode::Mirror.resolve(4, 4), Some(3));
+        for mode in BoundaryMode::ALL {
+            assert_eq!(mode.resolve(2, 4), Some(2));
+        }
+    }
+
+    #[test]
+    fn cells_decay_through_every_state() {
+        let rule = rule(&[], &[], 3, NeighborMode::Moore);
+        let mut grid = grid_with(&rule, &[[2, 2, 2]], 3);
+        let mut states = Vec::new();
+        for _ in 0..5 {
+            states.push(grid.get(2, 2, 2));
+            grid.step(&rule);
+        }
+        assert_eq!(states, [3, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn decaying_cells_are_not_neighbours() {
+        let rule = rule(&[], &[1], 3, NeighborMode::Moore);
+        let mut grid = grid_with(&rule, &[[2, 2, 2]], 2);
+        grid.step(&rule);
+        assert_eq!(grid.get(2, 2, 2), 1);
+        assert_eq!(grid.alive_count(&rule), 0);
+        assert_eq!(grid.cells().iter().filter(|&&cell| cell != 0).count(), 1);
+    }
+
+    #[test]
+    fn surviving_cells_stay_at_full_state() {
+        let rule = rule(&[0], &[], 4, NeighborMode::Moore);
+        let mut grid = grid_with(&rule, &[[0, 0, 0]], 4);
+        grid.step(&rule);
+        assert_eq!(grid.get(0, 0, 0), 4);
+    }
+
+    #[test]
+    fn menger_sponge_spawn() {
+        let rule = Rule {
+            size: UVec3::splat(27),
+            spawn_mode: SpawnMode::MengerSponge,
+            ..rule(&[], &[], 1, NeighborMode::Moore)
+        };
+        let grid = CpuGrid::new(&rule);
+        // Each level keeps 20 of the 27 sub-cubes.
+        assert_eq!(grid.alive_count(&rule), 20 * 20 * 20);
+        assert_eq!(grid.get(0, 0, 0), 1);
+        assert_eq!(grid.get(4, 0, 0), 1);
+        // The center of the sponge and of one of its faces.
+        assert_eq!(grid.get(13, 13, 13), 0);
+        assert_eq!(grid.get(13, 13, 0), 0);
+        // The same for the corner sub-cube.
+        assert_eq!(grid.get(4, 4, 4), 0);
+        assert_eq!(grid.get(4, 4, 0), 0);
+    }
+
+    #[test]
+    fn random_spawn_depends_on_the_seed() {
+        let rule = Rule {
+            spawn_mode: SpawnMode::Random(0.5, 1),
+            ..rule(&[], &[], 1, NeighborMode::Moore)
+        };
+        let grid = CpuGrid::new(&rule);
+        assert_eq!(grid, CpuGrid::new(&rule));
+        let alive = grid.alive_count(&rule);
+        assert!((50..166).contains(&alive), "{} of 216 alive", alive);
+
+        let reseeded = Rule {
+            spawn_mode: SpawnMode::Random(0.5, 2),
+            ..rule
+        };
+        assert_ne!(grid, CpuGrid::new(&reseeded));
+    }
+}
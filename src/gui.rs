@@ -1,5 +1,8 @@
+use std::marker::PhantomData;
+
 use bevy::{
     diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    ecs::system::SystemParam,
     prelude::*,
 };
 use bevy_egui::{
@@ -8,9 +11,19 @@ use bevy_egui::{
 };
 
 use crate::{
-    ca_compute::{ReInit, UpdateTime},
+    ca_compute::{Generation, Playback, ReInit, UpdateTime, MAX_GENERATIONS_PER_FRAME},
     fly_cam::MovementSettings,
-    rule::{ColorMode, ColorModeKind, NeighborMode, Rule, SpawnMode, SpawnModeKind, Value},
+    history::{grid_bytes, max_capacity, HistoryGenerations, HistorySettings, Rewind},
+    parse::{parse_value, ParseError, RuleFormat, MAX_STATES},
+    presets::{load_rule, preset_path, save_rule, Preset, Presets},
+    rule::{
+        random_seed, BoundaryMode, ColorMode, ColorModeKind, NeighborMode, NeighborModeKind,
+        NeighborWeights, NeighborWeightsKind, Rule, SpawnMode, SpawnModeKind, MAX_EXTENT,
+        MAX_RADIUS,
+    },
+    snapshot::{load_snapshot, snapshot_path, SnapshotRequest},
+    stats::{GenerationStats, Statistics, StatsRecorder, MAX_SAMPLES},
+    stop::StopConditions,
     Meshes, START_SENSITIVITY, START_SPEED,
 };
 
@@ -18,30 +31,167 @@ pub struct GuiPlugin;
 
 impl Plugin for GuiPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(EguiPlugin).add_system(egui_system);
+        app.add_plugin(EguiPlugin)
+            .add_system(egui_system.label(GuiSystem));
     }
 }
 
+/// Label of the systems that edit resources from the GUI.
+#[derive(SystemLabel)]
+pub struct GuiSystem;
+
 #[derive(Default)]
 struct State {
+    preset_search: String,
+    preset_spawn_and_color: bool,
+    file_name: String,
+    file_status: String,
     import: String,
+    import_format: RuleFormat,
+    import_error: Option<ParseError>,
     survival: String,
+    survival_error: Option<ParseError>,
     birth: String,
-    // size: u32,
+    birth_error: Option<ParseError>,
+    size: UVec3,
+    steps: u32,
+    csv_path: String,
+}
+
+#[derive(SystemParam)]
+struct HistoryParams<'w, 's> {
+    settings: Option<ResMut<'w, HistorySettings>>,
+    rewind: Option<ResMut<'w, Rewind>>,
+    kept: Option<Res<'w, HistoryGenerations>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+#[derive(SystemParam)]
+struct StatsParams<'w, 's> {
+    stats: Option<ResMut<'w, Statistics>>,
+    recorder: Option<ResMut<'w, StatsRecorder>>,
+    stop: Option<ResMut<'w, StopConditions>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
 }
 
 fn egui_system(
     mut ctx: ResMut<EguiContext>,
     rule: Option<ResMut<Rule>>,
     update_time: Option<ResMut<UpdateTime>>,
+    playback: Option<ResMut<Playback>>,
+    generation: Option<Res<Generation>>,
     reinit: Option<ResMut<ReInit>>,
     movement: Option<ResMut<MovementSettings>>,
     meshes: Option<ResMut<Meshes>>,
+    presets: Option<ResMut<Presets>>,
+    snapshots: Option<ResMut<SnapshotRequest>>,
+    history: HistoryParams,
+    mut stats: StatsParams,
     diagnostics: Res<Diagnostics>,
     mut state: Local<State>,
 ) {
+    let grid = rule
+        .as_ref()
+        .map(|rule| grid_bytes(rule.size, rule.cell_format()));
     egui::SidePanel::new(Side::Left, "settings").show(ctx.ctx_mut(), |ui| {
         if let Some(mut rule) = rule {
+            let mut preset_applied = false;
+            if let Some(mut presets) = presets {
+                ui.heading("Presets");
+                ui.horizontal(|ui| {
+                    ui.label("Search");
+                    ui.text_edit_singleline(&mut state.preset_search);
+                });
+                ui.checkbox(
+                    &mut state.preset_spawn_and_color,
+                    "Apply recommended spawn and color",
+                );
+                let search = state.preset_search.to_lowercase();
+                egui::ScrollArea::vertical()
+                    .max_height(150.0)
+                    .show(ui, |ui| {
+                        for preset in presets
+                            .0
+                            .iter()
+                            .filter(|preset| preset.name.to_lowercase().contains(&search))
+                        {
+                            if ui.selectable_label(false, preset.name.as_str()).clicked() {
+                                preset.apply(&mut rule, state.preset_spawn_and_color);
+                                preset_applied = state.preset_spawn_and_color;
+                            }
+                        }
+                    });
+                ui.end_row();
+
+                ui.horizontal(|ui| {
+                    ui.label("File");
+                    ui.text_edit_singleline(&mut state.file_name);
+                });
+                ui.horizontal(|ui| {
+                    let name = state.file_name.trim().to_string();
+                    if ui.button("Save").clicked() && !name.is_empty() {
+                        state.file_status = match save_rule(&rule, preset_path(&name)) {
+                            Ok(()) => {
+                                presets.insert(Preset {
+                                    name: name.clone(),
+                                    rule: rule.without_custom_cells(),
+                                });
+                                format!("Saved {}", preset_path(&name).display())
+                            }
+                            Err(e) => format!("Failed to save: {}", e),
+                        };
+                    }
+                    if ui.button("Load").clicked() && !name.is_empty() {
+                        state.file_status = match load_rule(preset_path(&name)) {
+                            Ok(loaded) => {
+                                *rule = loaded;
+                                preset_applied = true;
+                                format!("Loaded {}", preset_path(&name).display())
+                            }
+                            Err(e) => format!("Failed to load: {}", e),
+                        };
+                    }
+                });
+                if let (Some(mut snapshots), Some(generation)) = (snapshots, &generation) {
+                    ui.horizontal(|ui| {
+                        let name = state.file_name.trim().to_string();
+                        let saving = snapshots.save.is_some();
+                        if ui
+                            .add_enabled(!saving, egui::Button::new("Save snapshot"))
+                            .clicked()
+                            && !name.is_empty()
+                        {
+                            snapshots.request(snapshot_path(&name), generation);
+                            state.file_status = "Saving snapshot...".to_string();
+                        }
+                        if ui.button("Load snapshot").clicked() && !name.is_empty() {
+                            state.file_status = match load_snapshot(snapshot_path(&name)) {
+                                Ok(snapshot) => {
+                                    let status = format!(
+                                        "Loaded generation {} from {}",
+                                        snapshot.generation,
+                                        snapshot_path(&name).display()
+                                    );
+                                    snapshot.apply(&mut rule);
+                                    preset_applied = true;
+                                    status
+                                }
+                                Err(e) => format!("Failed to load snapshot: {}", e),
+                            };
+                        }
+                    });
+                    if let Some(status) = snapshots.status.take() {
+                        state.file_status = status;
+                    }
+                }
+                if !state.file_status.is_empty() {
+                    ui.label(state.file_status.as_str());
+                }
+                ui.end_row();
+            }
+
             if let Some(mut reinit) = reinit {
                 ui.heading("Spawn");
                 let mut mode = rule.spawn_mode.kind();
@@ -60,16 +210,41 @@ fn egui_system(
                         );
                     });
                 mode.update(&mut rule.spawn_mode);
+                let mut new_seed = false;
+                let size = rule.size;
                 match &mut rule.spawn_mode {
-                    SpawnMode::Random(f) => {
+                    SpawnMode::Random(f, seed) => {
                         *f = 1.0 - *f;
                         ui.add(egui::Slider::new(f, 0.0..=1.0));
                         *f = 1.0 - *f;
+                        ui.end_row();
+
+                        ui.horizontal(|ui| {
+                            ui.label("Seed");
+                            ui.add(egui::DragValue::new(seed));
+                            if ui.button("New seed").clicked() {
+                                *seed = random_seed();
+                                new_seed = true;
+                            }
+                        });
+                    }
+                    SpawnMode::Custom(cells) => {
+                        let cells_size = cells.grid.size();
+                        ui.label(format!(
+                            "{}x{}x{} cells",
+                            cells_size.x, cells_size.y, cells_size.z
+                        ));
+                        if cells_size != size {
+                            ui.colored_label(
+                                egui::Color32::RED,
+                                "Doesn't fit the grid, cells will stay dead",
+                            );
+                        }
                     }
                     _ => {}
                 }
                 ui.end_row();
-                reinit.0 = ui.button("Reset").clicked();
+                reinit.0 = ui.button("Reset").clicked() || new_seed || preset_applied;
             }
 
             ui.heading("Rule");
@@ -77,76 +252,175 @@ fn egui_system(
 
             ui.label("Import: ");
             let re = ui.text_edit_singleline(&mut state.import);
+            if re.changed() {
+                state.import_error = None;
+            }
             if re.lost_focus() && re.ctx.input().key_pressed(egui::Key::Enter) {
-                let mut items = state.import.trim().split('/');
-                if let (Some(survival), Some(birth), Some(states), Some(neighbor_mode)) = (
-                    items.next().and_then(Value::try_parse),
-                    items.next().and_then(Value::try_parse),
-                    items.next().and_then(|s| s.parse().ok()),
-                    items.next().and_then(|s| match s {
-                        "M" => Some(NeighborMode::Moore),
-                        "N" => Some(NeighborMode::VonNeumann),
-                        _ => None,
-                    }),
-                ) {
-                    rule.survival = survival;
-                    rule.birth = birth;
-                    rule.states = states;
-                    rule.neighbor_mode = neighbor_mode;
+                match rule.import(&state.import, state.import_format) {
+                    Ok(imported) => {
+                        *rule = imported;
+                        state.import.clear();
+                    }
+                    Err(e) => state.import_error = Some(e),
                 }
-                state.import.clear();
+            }
+            egui::ComboBox::from_label("Format")
+                .selected_text(match state.import_format {
+                    RuleFormat::Auto => {
+                        format!("Auto ({})", RuleFormat::detect(&state.import).as_str())
+                    }
+                    format => format.as_str().to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    for format in RuleFormat::ALL {
+                        ui.selectable_value(&mut state.import_format, format, format.as_str());
+                    }
+                });
+            if let Some(e) = &state.import_error {
+                ui.colored_label(egui::Color32::RED, e.to_string());
             }
 
-            // TODO: Fix resizing.
-            /*
-            ui.label("Size");
-            if state.size == 0 {
-                state.size = rule.size;
+            ui.label("Export: ");
+            let export = rule.export();
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut export.as_str()));
+                if ui.button("Copy").clicked() {
+                    ui.output().copied_text = export.clone();
+                }
+            });
+            // Rule strings don't cover the spawn, but the soup needs it to be reproduced.
+            if let SpawnMode::Random(chance, seed) = rule.spawn_mode {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "Random spawn, {:.0}% alive, seed {}",
+                        (1.0 - chance) * 100.0,
+                        seed
+                    ));
+                    if ui.button("Copy seed").clicked() {
+                        ui.output().copied_text = seed.to_string();
+                    }
+                });
             }
-            let res = ui.add(egui::Slider::new(&mut state.size, 2..=1024));
-            if res.lost_focus() || res.drag_released() {
-                rule.size = state.size;
+            ui.end_row();
+
+            // Resizing reallocates the volumes, so only apply it once the
+            // value is let go of.
+            ui.label("Size");
+            let mut extents = state.size.to_array();
+            let (mut apply, mut editing) = (false, false);
+            ui.horizontal(|ui| {
+                for (extent, axis) in extents.iter_mut().zip(["x: ", "y: ", "z: "]) {
+                    let res = ui.add(
+                        egui::DragValue::new(extent)
+                            .clamp_range(1..=MAX_EXTENT)
+                            .prefix(axis),
+                    );
+                    apply |= res.lost_focus() || res.drag_released();
+                    editing |= res.has_focus() || res.dragged();
+                }
+            });
+            if apply {
+                rule.size = UVec3::from(extents);
+            } else if !editing {
+                extents = rule.size.to_array();
             }
+            state.size = UVec3::from(extents);
             ui.end_row();
-            */
 
+            let max = rule.max_neighbors();
             ui.label("Survival");
             let survival = ui.text_edit_singleline(&mut state.survival);
             if survival.changed() {
-                if let Some(survival) = Value::try_parse(&state.survival) {
-                    rule.survival = survival;
+                match parse_value(&state.survival, 0, max) {
+                    Ok(survival) => {
+                        rule.survival = survival;
+                        state.survival_error = None;
+                    }
+                    Err(e) => state.survival_error = Some(e),
                 }
             } else if !survival.has_focus() {
                 state.survival = rule.survival.to_string();
+                state.survival_error = None;
+            }
+            if let Some(e) = &state.survival_error {
+                ui.colored_label(egui::Color32::RED, e.to_string());
             }
 
             ui.label("Birth");
             let birth = ui.text_edit_singleline(&mut state.birth);
             if birth.changed() {
-                if let Some(birth) = Value::try_parse(&state.birth) {
-                    rule.birth = birth;
+                match parse_value(&state.birth, 0, max) {
+                    Ok(birth) => {
+                        rule.birth = birth;
+                        state.birth_error = None;
+                    }
+                    Err(e) => state.birth_error = Some(e),
                 }
             } else if !birth.has_focus() {
                 state.birth = rule.birth.to_string();
+                state.birth_error = None;
+            }
+            if let Some(e) = &state.birth_error {
+                ui.colored_label(egui::Color32::RED, e.to_string());
             }
 
             ui.label("States");
-            ui.add(egui::Slider::new(&mut rule.states, 1..=40));
+            let format = rule.cell_format();
+            ui.add(
+                egui::Slider::new(&mut rule.states, 1..=MAX_STATES)
+                    .logarithmic(true)
+                    .text(format.as_str()),
+            );
             ui.end_row();
 
+            let mut mode = rule.neighbor_mode.kind();
             egui::ComboBox::from_label("Neighbor mode")
-                .selected_text(rule.neighbor_mode.as_str())
+                .selected_text(mode.as_str())
                 .show_ui(ui, |ui| {
-                    ui.selectable_value(
-                        &mut rule.neighbor_mode,
-                        NeighborMode::Moore,
-                        NeighborMode::Moore.as_str(),
-                    );
-                    ui.selectable_value(
-                        &mut rule.neighbor_mode,
-                        NeighborMode::VonNeumann,
-                        NeighborMode::VonNeumann.as_str(),
-                    );
+                    for kind in NeighborModeKind::ALL {
+                        ui.selectable_value(&mut mode, kind, kind.as_str());
+                    }
+                });
+            mode.update(&mut rule.neighbor_mode);
+            let Rule {
+                neighbor_mode,
+                radius,
+                ..
+            } = &mut *rule;
+            if let NeighborMode::Custom(mask) = neighbor_mode {
+                *radius = 1;
+                ui.end_row();
+                neighbor_mask_edit(ui, mask);
+            } else {
+                ui.label("Radius");
+                ui.add(egui::Slider::new(radius, 1..=MAX_RADIUS));
+            }
+            ui.end_row();
+
+            let mut weights = rule.weights.kind();
+            egui::ComboBox::from_label("Weights")
+                .selected_text(weights.as_str())
+                .show_ui(ui, |ui| {
+                    for kind in NeighborWeightsKind::ALL {
+                        ui.selectable_value(&mut weights, kind, kind.as_str());
+                    }
+                });
+            weights.update(&mut rule.weights);
+            neighbor_weights_edit(ui, &mut rule.weights);
+            ui.end_row();
+
+            // Counts a smaller neighborhood can't reach would make the exported
+            // rule fail to import.
+            let max = rule.max_neighbors();
+            rule.survival.clear_above(max);
+            rule.birth.clear_above(max);
+
+            egui::ComboBox::from_label("Boundary")
+                .selected_text(rule.boundary_mode.as_str())
+                .show_ui(ui, |ui| {
+                    for mode in BoundaryMode::ALL {
+                        ui.selectable_value(&mut rule.boundary_mode, mode, mode.as_str());
+                    }
                 });
             let mut mode = rule.color_mode.kind();
             egui::ComboBox::from_label("Color mode")
@@ -208,7 +482,17 @@ fn egui_system(
                         };
                         res
                     }
-                    _ => todo!(),
+                    // Edited in sRGB like the other colors, but kept linear
+                    // so loaded files aren't rewritten in another representation.
+                    Color::RgbaLinear { alpha, .. } => {
+                        let [red, green, blue, _] = color.as_rgba_f32();
+                        let mut rgb = [red, green, blue];
+                        let res = ui.color_edit_button_rgb(&mut rgb);
+                        if res.changed() {
+                            *color = Color::rgba(rgb[0], rgb[1], rgb[2], alpha).as_rgba_linear();
+                        }
+                        res
+                    }
                 }
             }
             match &mut rule.color_mode {
@@ -232,6 +516,42 @@ fn egui_system(
             }
         }
 
+        if let Some(mut playback) = playback {
+            playback_ui(ui, &mut playback, &mut state.steps);
+            if let (
+                Some(mut settings),
+                Some(mut rewind),
+                Some(kept),
+                Some(generation),
+                Some(grid),
+            ) = (
+                history.settings,
+                history.rewind,
+                history.kept,
+                &generation,
+                grid,
+            ) {
+                history_ui(
+                    ui,
+                    &mut settings,
+                    &mut rewind,
+                    &kept.get(),
+                    &mut playback,
+                    generation.get(),
+                    grid,
+                );
+            }
+        }
+        if let Some(mut statistics) = stats.stats {
+            stats_ui(
+                ui,
+                &mut statistics,
+                stats.recorder.as_deref_mut(),
+                stats.stop.as_deref_mut(),
+                &mut state.csv_path,
+            );
+        }
+
         ui.heading("Misc");
         if let Some(mut update_time) = update_time {
             ui.label("Update time");
@@ -273,5 +593,278 @@ fn egui_system(
                 .and_then(|diagnostic| diagnostic.smoothed())
                 .unwrap_or(0.0)
         ));
+        if let Some(generation) = generation {
+            ui.label(format!("Generation: {}", generation.get()));
+        }
+    });
+}
+
+fn playback_ui(ui: &mut egui::Ui, playback: &mut Playback, steps: &mut u32) {
+    ui.heading("Playback");
+    ui.horizontal(|ui| {
+        let label = if playback.paused { "Play" } else { "Pause" };
+        if ui.button(label).clicked() {
+            playback.paused = !playback.paused;
+        }
+        if ui.button("Step 1").clicked() {
+            playback.steps += 1;
+        }
+    });
+    ui.end_row();
+
+    if *steps == 0 {
+        *steps = 10;
+    }
+    ui.horizontal(|ui| {
+        if ui.button(format!("Step {}", steps)).clicked() {
+            playback.steps += *steps;
+        }
+        ui.add(egui::DragValue::new(steps).clamp_range(1..=100_000));
+        ui.label("generations");
+    });
+    if playback.steps > 0 {
+        ui.label(format!("{} steps left", playback.steps));
+    }
+    ui.end_row();
+
+    ui.label("Generations per frame");
+    ui.add_enabled(
+        !playback.fast,
+        egui::Slider::new(
+            &mut playback.generations_per_frame,
+            1..=MAX_GENERATIONS_PER_FRAME,
+        )
+        .logarithmic(true),
+    );
+    ui.checkbox(&mut playback.fast, "As fast as possible");
+    ui.end_row();
+}
+
+/// Rewinding to one of the `kept` generations, which pauses the playback.
+/// The capacity is limited to what fits in the history's memory budget with
+/// generations of `grid_bytes` each.
+fn history_ui(
+    ui: &mut egui::Ui,
+    settings: &mut HistorySettings,
+    rewind: &mut Rewind,
+    kept: &[u64],
+    playback: &mut Playback,
+    current: u64,
+    grid_bytes: u64,
+) {
+    ui.checkbox(&mut settings.enabled, "Keep history");
+    let max = max_capacity(grid_bytes);
+    if max == 0 {
+        ui.colored_label(egui::Color32::RED, "The grid is too large to keep history");
+    } else {
+        ui.horizontal(|ui| {
+            ui.add_enabled(
+                settings.enabled,
+                egui::Slider::new(&mut settings.capacity, 1..=max).text("generations"),
+            );
+            let bytes = settings.capacity.min(max) as u64 * grid_bytes;
+            ui.label(format!("{:.1} MiB", bytes as f64 / (1 << 20) as f64));
+        });
+    }
+    ui.end_row();
+
+    let previous = kept.iter().rev().find(|&&kept| kept < current).copied();
+    if ui
+        .add_enabled(previous.is_some(), egui::Button::new("Step back"))
+        .clicked()
+    {
+        rewind.0 = previous;
+        playback.paused = true;
+    }
+    if kept.len() > 1 {
+        let mut index = kept
+            .iter()
+            .position(|&kept| kept >= current)
+            .unwrap_or(kept.len() - 1);
+        let text = format!("Generation {}", kept[index]);
+        let slider = egui::Slider::new(&mut index, 0..=kept.len() - 1)
+            .show_value(false)
+            .text(text);
+        if ui.add(slider).changed() {
+            rewind.0 = Some(kept[index]);
+            playback.paused = true;
+        }
+    }
+    ui.end_row();
+}
+
+fn stats_ui(
+    ui: &mut egui::Ui,
+    stats: &mut Statistics,
+    recorder: Option<&mut StatsRecorder>,
+    stop: Option<&mut StopConditions>,
+    csv_path: &mut String,
+) {
+    ui.heading("Statistics");
+    ui.checkbox(&mut stats.enabled, "Collect statistics");
+    ui.end_row();
+    if let Some(recorder) = recorder {
+        ui.horizontal(|ui| {
+            ui.label("CSV");
+            match &recorder.path {
+                Some(path) => {
+                    ui.label(path.display().to_string());
+                    if ui.button("Stop").clicked() {
+                        recorder.path = None;
+                        recorder.status.clear();
+                    }
+                }
+                None => {
+                    ui.text_edit_singleline(csv_path);
+                    let path = csv_path.trim();
+                    if ui.button("Record").clicked() && !path.is_empty() {
+                        recorder.path = Some(path.into());
+                    }
+                }
+            }
+        });
+        if !recorder.status.is_empty() {
+            ui.label(recorder.status.as_str());
+        }
+        ui.end_row();
+    }
+    if let Some(stop) = stop {
+        ui.label("Stop when");
+        ui.checkbox(&mut stop.extinction, "Extinct");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut stop.stable, "Population unchanged for");
+            ui.add(
+                egui::DragValue::new(&mut stop.stable_generations)
+                    .clamp_range(1..=MAX_SAMPLES as u64),
+            );
+            ui.label("generations");
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut stop.periodic, "Repeating within");
+            ui.add(egui::DragValue::new(&mut stop.max_period).clamp_range(1..=MAX_SAMPLES as u64));
+            ui.label("generations");
+        });
+        if let Some(stopped) = &stop.stopped {
+            ui.colored_label(egui::Color32::YELLOW, stopped.as_str());
+        }
+        ui.end_row();
+    }
+    if let Some(latest) = stats.latest() {
+        ui.label(format!(
+            "Generation {}: {} alive, {} decaying",
+            latest.generation, latest.alive, latest.decaying
+        ));
+        if let (Some(births), Some(deaths)) = (latest.births, latest.deaths) {
+            ui.label(format!("{} births, {} deaths", births, deaths));
+        }
+        ui.end_row();
+
+        let line = |name: &str, value: fn(&GenerationStats) -> Option<u64>| {
+            let points: Vec<[f64; 2]> = stats
+                .samples
+                .iter()
+                .filter_map(|sample| Some([sample.generation as f64, value(sample)? as f64]))
+                .collect();
+            egui::plot::Line::new(points).name(name)
+        };
+        egui::plot::Plot::new("population")
+            .height(120.0)
+            .legend(egui::plot::Legend::default())
+            .show(ui, |plot_ui| {
+                plot_ui.line(line("Alive", |sample| Some(sample.alive)));
+                plot_ui.line(line("Decaying", |sample| Some(sample.decaying)));
+            });
+        egui::plot::Plot::new("births_deaths")
+            .height(120.0)
+            .legend(egui::plot::Legend::default())
+            .show(ui, |plot_ui| {
+                plot_ui.line(line("Births", |sample| sample.births));
+                plot_ui.line(line("Deaths", |sample| sample.deaths));
+            });
+        // Dead cells are left out, they would dwarf every other state.
+        if latest.states.len() > 1 {
+            let bars = latest.states[1..]
+                .iter()
+                .enumerate()
+                .map(|(i, &count)| egui::plot::Bar::new((i + 1) as f64, count as f64))
+                .collect();
+            egui::plot::Plot::new("states")
+                .height(120.0)
+                .show(ui, |plot_ui| {
+                    plot_ui.bar_chart(egui::plot::BarChart::new(bars).name("Cells per state"));
+                });
+        }
+        ui.end_row();
+    }
+}
+
+/// Shows the three z layers of the 3x3x3 cube around a cell next to each
+/// other, calling `cell` with the `NeighborMode::mask_bit` of every cell but
+/// the center.
+fn neighbor_cube(ui: &mut egui::Ui, id_source: &str, mut cell: impl FnMut(&mut egui::Ui, u32)) {
+    ui.horizontal(|ui| {
+        for z in -1..=1 {
+            egui::Grid::new((id_source, z))
+                .spacing([2.0, 2.0])
+                .show(ui, |ui| {
+                    for y in -1..=1 {
+                        for x in -1..=1 {
+                            match NeighborMode::mask_bit([x, y, z]) {
+                                Some(bit) => cell(ui, bit),
+                                None => {
+                                    ui.add_enabled(false, egui::SelectableLabel::new(false, "·"));
+                                }
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            ui.separator();
+        }
+    });
+}
+
+/// Editor for a custom neighborhood where clicking a cell toggles it.
+fn neighbor_mask_edit(ui: &mut egui::Ui, mask: &mut u32) {
+    ui.vertical(|ui| {
+        neighbor_cube(ui, "neighbor_mask", |ui, bit| {
+            let on = *mask & (1 << bit) != 0;
+            if ui.selectable_label(on, "■").clicked() {
+                *mask ^= 1 << bit;
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("All").clicked() {
+                *mask = NeighborMode::FULL_MASK;
+            }
+            if ui.button("Faces").clicked() {
+                *mask = NeighborMode::VonNeumann.mask();
+            }
+            if ui.button("Clear").clicked() {
+                *mask = 0;
+            }
+            ui.label(format!("{} neighbours", mask.count_ones()));
+        });
     });
 }
+
+fn neighbor_weights_edit(ui: &mut egui::Ui, weights: &mut NeighborWeights) {
+    match weights {
+        NeighborWeights::Uniform => {}
+        NeighborWeights::Classes { face, edge, corner } => {
+            ui.horizontal(|ui| {
+                ui.label("Face");
+                ui.add(egui::DragValue::new(face));
+                ui.label("Edge");
+                ui.add(egui::DragValue::new(edge));
+                ui.label("Corner");
+                ui.add(egui::DragValue::new(corner));
+            });
+        }
+        NeighborWeights::Offsets(offsets) => {
+            neighbor_cube(ui, "neighbor_weights", |ui, bit| {
+                ui.add(egui::DragValue::new(&mut offsets[bit as usize]));
+            });
+        }
+    }
+}
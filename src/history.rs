@@ -0,0 +1,267 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::*,
+        renderer::RenderDevice,
+        texture::GpuImage,
+        RenderApp, RenderStage,
+    },
+};
+
+use crate::rule::{CellFormat, Rule};
+
+/// Keeps copies of the last generations on the GPU so they can be rewound to.
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        let generations = HistoryGenerations::default();
+        app.init_resource::<HistorySettings>()
+            .init_resource::<Rewind>()
+            .insert_resource(generations.clone())
+            .add_system_to_stage(CoreStage::First, clear_rewind);
+        app.add_plugin(ExtractResourcePlugin::<HistorySettings>::default())
+            .add_plugin(ExtractResourcePlugin::<Rewind>::default());
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .insert_resource(History {
+                entries: Mutex::default(),
+                generations,
+            })
+            .add_system_to_stage(RenderStage::Prepare, apply_settings);
+    }
+}
+
+/// The most generations the history can be set to keep.
+pub const MAX_HISTORY: usize = 256;
+/// Video memory the kept generations may take up together, large grids keep
+/// fewer generations than the capacity asks for.
+pub const HISTORY_BUDGET: u64 = 1 << 30;
+
+/// Bytes one kept generation of a grid of `size` cells takes up.
+pub fn grid_bytes(size: UVec3, format: CellFormat) -> u64 {
+    size.x as u64 * size.y as u64 * size.z as u64 * format.pixel_size() as u64
+}
+
+/// The most generations of `grid_bytes` each that fit in `HISTORY_BUDGET`.
+pub fn max_capacity(grid_bytes: u64) -> usize {
+    (HISTORY_BUDGET / grid_bytes.max(1)).min(MAX_HISTORY as u64) as usize
+}
+
+#[derive(Resource, Clone, ExtractResource)]
+pub struct HistorySettings {
+    /// Off by default, since every generation kept is as large as the grid.
+    pub enabled: bool,
+    /// Generations kept, the oldest are dropped first.
+    pub capacity: usize,
+}
+
+impl HistorySettings {
+    /// Generations actually kept of `grid_bytes` each, none when disabled.
+    pub fn effective_capacity(&self, grid_bytes: u64) -> usize {
+        if self.enabled {
+            self.capacity.min(max_capacity(grid_bytes))
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 16,
+        }
+    }
+}
+
+/// Set to a generation in `HistoryGenerations` to go back to it. Cleared at
+/// the start of every frame.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct Rewind(pub Option<u64>);
+
+/// The generations that can be rewound to, oldest first. Shared between the
+/// main and the render world, where `History` keeps it up to date.
+#[derive(Resource, Clone, Default)]
+pub struct HistoryGenerations(Arc<Mutex<Vec<u64>>>);
+
+impl HistoryGenerations {
+    pub fn get(&self) -> Vec<u64> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+fn clear_rewind(mut rewind: ResMut<Rewind>) {
+    rewind.0 = None;
+}
+
+struct Entry {
+    texture: Texture,
+    format: CellFormat,
+    size: UVec3,
+    generation: u64,
+}
+
+/// The kept generations in the render world. Behind a mutex since
+/// `DispatchCA` records and restores them with only shared access to the world.
+#[derive(Resource)]
+pub struct History {
+    entries: Mutex<VecDeque<Entry>>,
+    generations: HistoryGenerations,
+}
+
+impl History {
+    /// Forgets every generation, e.g. when the automaton starts over.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clear();
+        self.publish(&entries);
+    }
+
+    fn publish(&self, entries: &VecDeque<Entry>) {
+        *self.generations.0.lock().unwrap() =
+            entries.iter().map(|entry| entry.generation).collect();
+    }
+
+    /// Copies `image`, which holds `generation`, into the history. Generations
+    /// from after it are dropped, they belong to a timeline that was rewound.
+    pub fn record(
+        &self,
+        world: &World,
+        encoder: &mut CommandEncoder,
+        image: &GpuImage,
+        format: CellFormat,
+        size: UVec3,
+        generation: u64,
+    ) {
+        let Some(settings) = world.get_resource::<HistorySettings>() else {
+            return;
+        };
+        let capacity = settings.effective_capacity(grid_bytes(size, format));
+        if capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|entry| entry.generation < generation);
+
+        // Reuse the texture of the oldest generation once full.
+        let mut reused = None;
+        while entries.len() >= capacity {
+            reused = entries.pop_front();
+        }
+        let texture = match reused {
+            Some(entry) if entry.format == format && entry.size == size => entry.texture,
+            _ => world
+                .resource::<RenderDevice>()
+                .create_texture(&TextureDescriptor {
+                    label: Some("history"),
+                    size: extent(size),
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D3,
+                    format: format.texture_format(),
+                    usage: TextureUsages::COPY_SRC | TextureUsages::COPY_DST,
+                }),
+        };
+        encoder.copy_texture_to_texture(
+            copy_view(&image.texture),
+            copy_view(&texture),
+            extent(size),
+        );
+        entries.push_back(Entry {
+            texture,
+            format,
+            size,
+            generation,
+        });
+        self.publish(&entries);
+    }
+
+    /// Copies `generation` back into `image`, returning whether it was kept.
+    pub fn restore(
+        &self,
+        encoder: &mut CommandEncoder,
+        image: &GpuImage,
+        format: CellFormat,
+        size: UVec3,
+        generation: u64,
+    ) -> bool {
+        let entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.iter().find(|entry| {
+            entry.generation == generation && entry.format == format && entry.size == size
+        }) else {
+            return false;
+        };
+        encoder.copy_texture_to_texture(
+            copy_view(&entry.texture),
+            copy_view(&image.texture),
+            extent(size),
+        );
+        true
+    }
+}
+
+fn copy_view(texture: &Texture) -> ImageCopyTexture {
+    ImageCopyTexture {
+        texture,
+        mip_level: 0,
+        origin: Origin3d::ZERO,
+        aspect: TextureAspect::All,
+    }
+}
+
+fn extent(size: UVec3) -> Extent3d {
+    Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: size.z,
+    }
+}
+
+/// Frees kept generations as soon as the history is turned off or shrunk,
+/// rather than on the next generation.
+fn apply_settings(
+    history: Res<History>,
+    settings: Option<Res<HistorySettings>>,
+    rule: Option<Res<Rule>>,
+) {
+    let (Some(settings), Some(rule)) = (settings, rule) else {
+        return;
+    };
+    let mut entries = history.entries.lock().unwrap();
+    let capacity = settings.effective_capacity(grid_bytes(rule.size, rule.cell_format()));
+    if entries.len() > capacity {
+        let excess = entries.len() - capacity;
+        entries.drain(..excess);
+        history.publish(&entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::MAX_EXTENT;
+
+    #[test]
+    fn large_grids_keep_fewer_generations() {
+        let bytes = |extent, format| grid_bytes(UVec3::splat(extent), format);
+        assert_eq!(max_capacity(bytes(64, CellFormat::R8)), MAX_HISTORY);
+        assert_eq!(max_capacity(bytes(512, CellFormat::R8)), 8);
+        assert_eq!(max_capacity(bytes(512, CellFormat::R32)), 2);
+        assert_eq!(max_capacity(bytes(MAX_EXTENT, CellFormat::R16)), 0);
+
+        let settings = HistorySettings {
+            enabled: true,
+            capacity: 16,
+        };
+        assert_eq!(settings.effective_capacity(bytes(64, CellFormat::R8)), 16);
+        assert_eq!(settings.effective_capacity(bytes(512, CellFormat::R8)), 8);
+    }
+}
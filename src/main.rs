@@ -1,23 +1,81 @@
 mod ca_compute;
+mod cpu;
 mod fly_cam;
 mod gui;
+mod history;
+mod parse;
+mod presets;
+mod readback;
 mod rtmaterial;
 mod rule;
+mod snapshot;
+mod stats;
+mod stop;
 
 use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*, render::render_resource::*};
 use ca_compute::{CAImage, CAPlugin};
 use fly_cam::{MovementSettings, PlayerPlugin};
-use gui::GuiPlugin;
+use gui::{GuiPlugin, GuiSystem};
+use history::HistoryPlugin;
+use parse::RuleFormat;
+use readback::ReadbackPlugin;
 use rtmaterial::{RTMatPlugin, RTVolumeMaterial};
-use rule::{Rule, RulePlugin};
-
-const WORKGROUP_SIZE: u32 = 9;
+use rule::{Rule, RulePlugin, MAX_EXTENT};
+use snapshot::SnapshotPlugin;
+use stats::{StatsPlugin, StatsRecorder};
+use stop::StopPlugin;
 
 const START_SPEED: f32 = 0.1;
 const START_SENSITIVITY: f32 = 0.0004;
 
+const USAGE: &str = "\
+Usage: cas [--rule RULE] [--rule-format FORMAT] [--size SIZE] [--stats-csv PATH]
+
+  --rule RULE            e.g. 9-26/5-7,12,13,15/5/M, 4/4/5/VN or B4/S4,5
+  --rule-format FORMAT   auto, slashed or bays, defaults to auto
+  --size SIZE            e.g. 243 or 512x512x16
+  --stats-csv PATH       append statistics of every generation to PATH";
+
+/// Command line arguments, e.g. `cas --rule B4/S4,5 --size 512x512x16 --stats-csv run.csv`.
+#[derive(Resource, Default)]
+struct Args {
+    rule: Option<String>,
+    rule_format: RuleFormat,
+    size: Option<String>,
+    stats_csv: Option<String>,
+}
+
+impl Args {
+    fn parse() -> Self {
+        let mut res = Self::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--rule" => res.rule = args.next(),
+                "--rule-format" => {
+                    let format = args.next().unwrap_or_default();
+                    match RuleFormat::from_arg_name(&format) {
+                        Some(format) => res.rule_format = format,
+                        None => eprintln!("Unknown rule format `{}`\n{}", format, USAGE),
+                    }
+                }
+                "--size" => res.size = args.next(),
+                "--stats-csv" => res.stats_csv = args.next(),
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => eprintln!("Unknown argument `{}`\n{}", arg, USAGE),
+            }
+        }
+        res
+    }
+}
+
 fn main() {
     App::new()
+        .insert_resource(Args::parse())
+        .add_startup_system(apply_args.before(setup))
         .add_startup_system(setup)
         .insert_resource(ClearColor(Color::BLACK))
         .add_plugins(
@@ -45,16 +103,19 @@ fn main() {
         .add_plugin(PlayerPlugin)
         .add_plugin(RulePlugin)
         .add_plugin(CAPlugin)
+        .add_plugin(ReadbackPlugin)
+        .add_plugin(SnapshotPlugin)
+        .add_plugin(HistoryPlugin)
+        .add_plugin(StatsPlugin)
+        .add_plugin(StopPlugin)
         .add_plugin(RTMatPlugin)
         .add_plugin(GuiPlugin)
-        .add_system(update_size)
+        // After the GUI, so the volumes are resized the same frame as the rule.
+        .add_system(update_volumes.after(GuiSystem))
         .add_system(update_shape)
         .run();
 }
 
-#[derive(Resource)]
-struct CurrentSize(u32);
-
 #[derive(Resource)]
 struct Meshes {
     meshes: Vec<(&'static str, Handle<Mesh>)>,
@@ -68,18 +129,7 @@ fn setup(
     mut meshes: ResMut<Assets<Mesh>>,
     rule: Res<Rule>,
 ) {
-    let mut image = Image::new_fill(
-        Extent3d {
-            width: rule.size,
-            height: rule.size,
-            depth_or_array_layers: rule.size,
-        },
-        bevy::render::render_resource::TextureDimension::D3,
-        &[0],
-        TextureFormat::R8Uint,
-    );
-    image.texture_descriptor.usage =
-        TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST;
+    let image = cell_image(&rule);
 
     let meshes = Meshes {
         current: 0,
@@ -104,37 +154,120 @@ fn setup(
             ),
         ],
     };
-    let image = images.add(image);
+    let image = [images.add(image.clone()), images.add(image)];
     commands.spawn(MaterialMeshBundle::<RTVolumeMaterial> {
         mesh: meshes.meshes[meshes.current].1.clone(),
         material: materials.add(RTVolumeMaterial {
-            volume: Some(image.clone()),
+            volumes: Some(image.clone()),
             rule: rule.clone(),
         }),
+        transform: Transform::from_scale(rule.aspect()),
         ..default()
     });
 
     commands.insert_resource(meshes);
-    commands.insert_resource(CurrentSize(rule.size));
-    commands.insert_resource(CAImage(image));
+    commands.insert_resource(CAImage {
+        volumes: image,
+        size: rule.size,
+    });
+}
+
+/// An empty volume for `rule`, in the format its states need.
+fn cell_image(rule: &Rule) -> Image {
+    let format = rule.cell_format();
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: rule.size.x,
+            height: rule.size.y,
+            depth_or_array_layers: rule.size.z,
+        },
+        bevy::render::render_resource::TextureDimension::D3,
+        &vec![0; format.pixel_size()],
+        format.texture_format(),
+    );
+    image.texture_descriptor.usage = TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING
+        | TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC;
+    image
+}
+
+fn apply_args(args: Res<Args>, mut rule: ResMut<Rule>, mut recorder: ResMut<StatsRecorder>) {
+    if let Some(s) = &args.rule {
+        match rule.import(s, args.rule_format) {
+            Ok(imported) => *rule = imported,
+            Err(e) => error!("Invalid rule `{}`: {}", s, e),
+        }
+    }
+    if let Some(s) = &args.size {
+        match parse_size(s) {
+            Some(size) => rule.size = size,
+            None => error!(
+                "Invalid size `{}`, expected e.g. 243 or 512x512x16, up to {}",
+                s, MAX_EXTENT
+            ),
+        }
+    }
+    if let Some(path) = &args.stats_csv {
+        recorder.path = Some(path.into());
+    }
 }
 
-fn update_size(
-    image: Res<CAImage>,
+/// Parses `X`x`Y`x`Z`, or a single number for a cube.
+fn parse_size(s: &str) -> Option<UVec3> {
+    let extents = s
+        .split('x')
+        .map(|extent| {
+            extent
+                .trim()
+                .parse()
+                .ok()
+                .filter(|extent| (1..=MAX_EXTENT).contains(extent))
+        })
+        .collect::<Option<Vec<u32>>>()?;
+    match extents[..] {
+        [size] => Some(UVec3::splat(size)),
+        [x, y, z] => Some(UVec3::new(x, y, z)),
+        _ => None,
+    }
+}
+
+/// Reallocates the volumes when the rule needs a different size or cell
+/// format, and rebuilds the material's bind group so it points at the new
+/// textures. `DispatchCA` re-initializes the automaton once it sees them.
+fn update_volumes(
+    mut image: ResMut<CAImage>,
     mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<RTVolumeMaterial>>,
+    mut query: Query<(&Handle<RTVolumeMaterial>, &mut Transform)>,
     rule: Res<Rule>,
-    mut size: ResMut<CurrentSize>,
 ) {
-    if size.0 != rule.size {
-        size.0 = rule.size;
-        if let Some(image) = images.get_mut(&image.0) {
-            image.resize(Extent3d {
-                width: rule.size,
-                height: rule.size,
-                depth_or_array_layers: rule.size,
-            });
+    let size = Extent3d {
+        width: rule.size.x,
+        height: rule.size.y,
+        depth_or_array_layers: rule.size.z,
+    };
+    let format = rule.cell_format().texture_format();
+    // Only borrow mutably when needed, since that marks the asset as modified.
+    let stale = image.volumes.iter().any(|image| {
+        images.get(image).map_or(false, |image| {
+            image.texture_descriptor.size != size || image.texture_descriptor.format != format
+        })
+    });
+    if !stale {
+        return;
+    }
+    for image in &image.volumes {
+        if let Some(image) = images.get_mut(image) {
+            *image = cell_image(&rule);
         }
     }
+    image.size = rule.size;
+    for (material, mut transform) in query.iter_mut() {
+        // Marking the material as modified is enough for its bind group to be rebuilt.
+        materials.get_mut(material);
+        transform.scale = rule.aspect();
+    }
 }
 
 fn update_shape(meshes: Res<Meshes>, mut mesh: Query<&mut Handle<Mesh>>, mut last: Local<usize>) {
@@ -0,0 +1,477 @@
+use std::fmt;
+
+use crate::rule::{NeighborMode, NeighborWeights, Value, MAX_RADIUS};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidNumber,
+    OutOfRange { value: usize, max: usize },
+    ReversedRange { start: usize, end: usize },
+    MissingPart(&'static str),
+    InvalidNeighborMode,
+    InvalidStates { min: u32, max: u32 },
+    InvalidRadius,
+    InvalidWeights,
+    UnknownPart,
+    TrailingInput,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidNumber => write!(f, "expected a number"),
+            Self::OutOfRange { value, max } => {
+                write!(f, "{} is more than the {} neighbours", value, max)
+            }
+            Self::ReversedRange { start, end } => write!(f, "range {}-{} is reversed", start, end),
+            Self::MissingPart(part) => write!(f, "missing {}", part),
+            Self::InvalidNeighborMode => {
+                write!(f, "expected a neighbor mode like M, VN or X3ffffff")
+            }
+            Self::InvalidStates { min, max } => {
+                write!(f, "states must be between {} and {}", min, max)
+            }
+            Self::InvalidRadius => write!(f, "radius must be between 1 and {}", MAX_RADIUS),
+            Self::InvalidWeights => write!(f, "expected 3 or 26 weights between 0 and 255"),
+            Self::UnknownPart => write!(
+                f,
+                "expected a part starting with B, S, C, R, W or a neighbor mode"
+            ),
+            Self::TrailingInput => write!(f, "unexpected trailing input"),
+        }
+    }
+}
+
+/// An error pointing at the part of the input that could not be parsed.
+/// `position` is the byte offset of `segment` in the whole input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub segment: String,
+    pub position: usize,
+}
+
+impl ParseError {
+    fn new(kind: ParseErrorKind, segment: &str, position: usize) -> Self {
+        Self {
+            kind,
+            segment: segment.to_string(),
+            position,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at position {}: `{}`",
+            self.kind, self.position, self.segment
+        )
+    }
+}
+
+/// The most states a cell can have. Cells are stored in up to 32 bits, but
+/// the shaders do their arithmetic on `i32`s.
+pub const MAX_STATES: u32 = i32::MAX as u32;
+
+/// The fields of a `Rule` covered by rule strings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsedRule {
+    pub survival: Value,
+    pub birth: Value,
+    pub states: u32,
+    pub neighbor_mode: NeighborMode,
+    pub radius: u32,
+    pub weights: NeighborWeights,
+}
+
+/// Splits `s` on `separator`, keeping the byte offset of each trimmed part.
+fn split_with_positions(s: &str, separator: char, start: usize) -> Vec<(&str, usize)> {
+    let mut offset = start;
+    s.split(separator)
+        .map(|part| {
+            let position = offset + (part.len() - part.trim_start().len());
+            offset += part.len() + separator.len_utf8();
+            (part.trim(), position)
+        })
+        .collect()
+}
+
+fn parse_number(s: &str, position: usize) -> Result<usize, ParseError> {
+    s.parse()
+        .map_err(|_| ParseError::new(ParseErrorKind::InvalidNumber, s, position))
+}
+
+/// Parses a comma separated list of neighbour counts and ranges like
+/// `4,9-26`, where no count may be larger than `max`. `start` is the offset of
+/// `s` in a larger input and is only used for error positions.
+pub fn parse_value(s: &str, start: usize, max: usize) -> Result<Value, ParseError> {
+    let mut res = Value::default();
+    if s.trim().is_empty() {
+        return Ok(res);
+    }
+    let check = |value: usize, segment: &str, position: usize| {
+        if value > max {
+            Err(ParseError::new(
+                ParseErrorKind::OutOfRange { value, max },
+                segment,
+                position,
+            ))
+        } else {
+            Ok(value)
+        }
+    };
+    for (item, position) in split_with_positions(s, ',', start) {
+        if let Some((r0, r1)) = item.split_once('-') {
+            let r1_position = position + r0.len() + 1 + (r1.len() - r1.trim_start().len());
+            let (r0, r1) = (r0.trim_end(), r1.trim_start());
+            let r0 = check(parse_number(r0, position)?, r0, position)?;
+            let r1 = check(parse_number(r1, r1_position)?, r1, r1_position)?;
+            if r0 > r1 {
+                return Err(ParseError::new(
+                    ParseErrorKind::ReversedRange { start: r0, end: r1 },
+                    item,
+                    position,
+                ));
+            }
+            for i in r0..=r1 {
+                res.set(i, true);
+            }
+        } else {
+            let value = check(parse_number(item, position)?, item, position)?;
+            res.set(value, true);
+        }
+    }
+    Ok(res)
+}
+
+/// The notations rules can be written in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RuleFormat {
+    /// Pick one of the others by looking at the input.
+    #[default]
+    Auto,
+    /// `survival/birth/states/neighborhood` as in the literature and Visions
+    /// of Chaos, e.g. `9-26/5-7,12,13,15/5/M` or `0-6/1,3/2/VN`, where the
+    /// state count includes the dead state. This is what `Rule::export` writes.
+    Slashed,
+    /// Bays style parts prefixed by letters in any order, e.g. `B4/S4,5`,
+    /// optionally with `C<states>` (including the dead state) and a neighbor mode.
+    Bays,
+}
+
+impl RuleFormat {
+    pub const ALL: [RuleFormat; 3] = [Self::Auto, Self::Slashed, Self::Bays];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Auto => "Auto",
+            Self::Slashed => "S/B/C/N",
+            Self::Bays => "Bays (B/S)",
+        }
+    }
+
+    /// The name of the format on the command line.
+    pub fn arg_name(&self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Slashed => "slashed",
+            Self::Bays => "bays",
+        }
+    }
+
+    pub fn from_arg_name(s: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.arg_name().eq_ignore_ascii_case(s.trim()))
+    }
+
+    /// Guesses the format of `s`, Bays if any part starts with `B` or `S`.
+    pub fn detect(s: &str) -> RuleFormat {
+        let is_bays = s.split('/').map(str::trim).any(|part| {
+            part.chars()
+                .next()
+                .map_or(false, |c| matches!(c.to_ascii_uppercase(), 'B' | 'S'))
+        });
+        if is_bays {
+            Self::Bays
+        } else {
+            Self::Slashed
+        }
+    }
+}
+
+/// Splits a neighborhood like `M2` into its name and radius digits.
+fn split_radius(s: &str) -> (&str, &str) {
+    let name = s.trim_end_matches(|c: char| c.is_ascii_digit());
+    (name.trim_end(), &s[name.len()..])
+}
+
+/// Parses radius digits, where no digits means a radius of 1.
+fn parse_radius(s: &str, position: usize) -> Result<u32, ParseError> {
+    if s.is_empty() {
+        return Ok(1);
+    }
+    match s.parse() {
+        Ok(radius) if (1..=MAX_RADIUS).contains(&radius) => Ok(radius),
+        _ => Err(ParseError::new(ParseErrorKind::InvalidRadius, s, position)),
+    }
+}
+
+/// Parses a custom neighborhood written as `X` and a hex mask, e.g. `X3ffffff`.
+fn parse_custom_mask(s: &str) -> Option<NeighborMode> {
+    let hex = s.strip_prefix('X').or_else(|| s.strip_prefix('x'))?;
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|&mask| mask <= NeighborMode::FULL_MASK)
+        .map(NeighborMode::Custom)
+}
+
+/// Parses a neighborhood with an optional radius like `M2`, returning the
+/// neighbor mode and radius. Custom neighborhoods always have a radius of 1.
+fn parse_neighborhood(s: &str, position: usize) -> Result<(NeighborMode, u32), ParseError> {
+    let invalid = || ParseError::new(ParseErrorKind::InvalidNeighborMode, s, position);
+    if s.starts_with(['X', 'x']) {
+        return parse_custom_mask(s)
+            .map(|mode| (mode, 1))
+            .ok_or_else(invalid);
+    }
+    let (name, radius) = split_radius(s);
+    let radius = parse_radius(radius, position + name.len())?;
+    parse_neighbor_mode_name(name)
+        .map(|mode| (mode, radius))
+        .ok_or_else(invalid)
+}
+
+/// Parses weights like `W2,1,1` for face, edge and corner neighbours, or 26
+/// comma separated weights, one per radius 1 offset.
+fn parse_weights(s: &str, position: usize) -> Result<NeighborWeights, ParseError> {
+    let invalid = || ParseError::new(ParseErrorKind::InvalidWeights, s, position);
+    let rest = s.strip_prefix(['W', 'w']).ok_or_else(invalid)?;
+    let mut weights = Vec::new();
+    for (weight, weight_position) in split_with_positions(rest, ',', position + 1) {
+        let weight = parse_number(weight, weight_position)?;
+        weights.push(u8::try_from(weight).map_err(|_| invalid())?);
+    }
+    match weights[..] {
+        [face, edge, corner] => Ok(NeighborWeights::Classes { face, edge, corner }),
+        _ => weights
+            .try_into()
+            .map(NeighborWeights::Offsets)
+            .map_err(|_| invalid()),
+    }
+}
+
+/// Parses the neighbor mode names used by other programs and papers.
+fn parse_neighbor_mode_name(s: &str) -> Option<NeighborMode> {
+    let name: String = s
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_'))
+        .collect::<String>()
+        .to_lowercase();
+    match name.as_str() {
+        "m" | "moore" => Some(NeighborMode::Moore),
+        "n" | "vn" | "nn" | "vonneumann" | "neumann" => Some(NeighborMode::VonNeumann),
+        _ => None,
+    }
+}
+
+fn parse_states(s: &str, position: usize, includes_dead: bool) -> Result<u32, ParseError> {
+    let (min, max) = if includes_dead {
+        (2, MAX_STATES + 1)
+    } else {
+        (1, MAX_STATES)
+    };
+    let states = parse_number(s, position)?;
+    if !(min as usize..=max as usize).contains(&states) {
+        return Err(ParseError::new(
+            ParseErrorKind::InvalidStates { min, max },
+            s,
+            position,
+        ));
+    }
+    Ok(states as u32 - includes_dead as u32)
+}
+
+/// Parses a rule string in `format`, detecting it first if it is `RuleFormat::Auto`.
+pub fn parse_rule(s: &str, format: RuleFormat) -> Result<ParsedRule, ParseError> {
+    match format {
+        RuleFormat::Auto => parse_rule(s, RuleFormat::detect(s)),
+        RuleFormat::Slashed => parse_slashed(s),
+        RuleFormat::Bays => parse_bays(s),
+    }
+}
+
+/// Parses `survival/birth/states/neighborhood` with optional weights as a
+/// fifth part. The states include the dead state.
+fn parse_slashed(s: &str) -> Result<ParsedRule, ParseError> {
+    let parts = split_with_positions(s, '/', 0);
+    let end = s.trim_end().len();
+    let part = |i: usize, name: &'static str| {
+        parts
+            .get(i)
+            .copied()
+            .ok_or_else(|| ParseError::new(ParseErrorKind::MissingPart(name), "", end))
+    };
+    let (survival, survival_position) = part(0, "survival")?;
+    let (birth, birth_position) = part(1, "birth")?;
+    let (states, states_position) = part(2, "states")?;
+    let (neighbor_mode, neighbor_mode_position) = part(3, "neighbor mode")?;
+    let weights = match parts.get(4) {
+        Some((weights, position)) => parse_weights(weights, *position)?,
+        None => NeighborWeights::Uniform,
+    };
+    if let Some((trailing, position)) = parts.get(5) {
+        return Err(ParseError::new(
+            ParseErrorKind::TrailingInput,
+            trailing,
+            *position,
+        ));
+    }
+
+    let (neighbor_mode, radius) = parse_neighborhood(neighbor_mode, neighbor_mode_position)?;
+    let max = weights.max_count(neighbor_mode, radius);
+
+    Ok(ParsedRule {
+        survival: parse_value(survival, survival_position, max)?,
+        birth: parse_value(birth, birth_position, max)?,
+        states: parse_states(states, states_position, true)?,
+        neighbor_mode,
+        radius,
+        weights,
+    })
+}
+
+/// Parses Bays style rules like `B4/S4,5`, `S4-5/B4/C5/VN`, `b5,6 / s4` or
+/// `B10-20/S12-30/R2/W2,1,1`.
+fn parse_bays(s: &str) -> Result<ParsedRule, ParseError> {
+    let mut survival = None;
+    let mut birth = None;
+    let mut states = 1;
+    let mut neighbor_mode = None;
+    let mut radius = 1;
+    let mut weights = NeighborWeights::Uniform;
+    for (part, position) in split_with_positions(s, '/', 0) {
+        if part.is_empty() {
+            continue;
+        }
+        if let Some(mode) = parse_custom_mask(part) {
+            neighbor_mode = Some(mode);
+            radius = 1;
+            continue;
+        }
+        let (name, digits) = split_radius(part);
+        if let Some(mode) = parse_neighbor_mode_name(name) {
+            neighbor_mode = Some(mode);
+            radius = parse_radius(digits, position + name.len())?;
+            continue;
+        }
+        let mut chars = part.chars();
+        let letter = chars.next().unwrap_or_default();
+        let rest = chars.as_str();
+        let rest_position =
+            position + letter.len_utf8() + (rest.len() - rest.trim_start().len());
+        let rest = rest.trim();
+        match letter.to_ascii_uppercase() {
+            'S' => survival = Some((rest, rest_position)),
+            'B' => birth = Some((rest, rest_position)),
+            'C' | 'G' => states = parse_states(rest, rest_position, true)?,
+            'R' => radius = parse_radius(rest, rest_position)?,
+            'W' => weights = parse_weights(part, position)?,
+            _ => return Err(ParseError::new(ParseErrorKind::UnknownPart, part, position)),
+        }
+    }
+
+    let neighbor_mode = neighbor_mode.unwrap_or(NeighborMode::Moore);
+    let max = weights.max_count(neighbor_mode, radius);
+    let value = |part: Option<(&str, usize)>| match part {
+        Some((s, position)) => parse_value(s, position, max),
+        None => Ok(Value::default()),
+    };
+
+    Ok(ParsedRule {
+        survival: value(survival)?,
+        birth: value(birth)?,
+        states,
+        neighbor_mode,
+        radius,
+        weights,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(RuleFormat::detect("9-26/5-7/4/M"), RuleFormat::Slashed);
+        assert_eq!(
+            RuleFormat::detect("9-26/5-7/4/X3ffffff"),
+            RuleFormat::Slashed
+        );
+        assert_eq!(RuleFormat::detect("0-6/1,3/2/VN"), RuleFormat::Slashed);
+        assert_eq!(RuleFormat::detect("B4/S4,5"), RuleFormat::Bays);
+        assert_eq!(RuleFormat::detect("s4-5 / b4 / c5"), RuleFormat::Bays);
+    }
+
+    #[test]
+    fn slashed_states_include_the_dead_state() {
+        for s in ["4/4/5/M", "4/4/5/Moore", "4/4/5/moore1"] {
+            let rule = parse_rule(s, RuleFormat::Auto).unwrap();
+            assert_eq!(rule.states, 4, "{}", s);
+            assert_eq!(rule.neighbor_mode, NeighborMode::Moore, "{}", s);
+            assert_eq!(rule.radius, 1, "{}", s);
+        }
+        let rule = parse_rule("0-6/1,3/2/VN2", RuleFormat::Auto).unwrap();
+        assert_eq!(rule.states, 1);
+        assert_eq!(rule.neighbor_mode, NeighborMode::VonNeumann);
+        assert_eq!(rule.radius, 2);
+        let e = parse_rule("4/4/1/M", RuleFormat::Auto).unwrap_err();
+        assert!(matches!(
+            e.kind,
+            ParseErrorKind::InvalidStates { min: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn parses_bays() {
+        let rule = parse_rule("B4/S4,5/C6/VN2", RuleFormat::Auto).unwrap();
+        assert_eq!(rule.birth, Value::from(vec![4]));
+        assert_eq!(rule.survival, Value::from(vec![4, 5]));
+        assert_eq!(rule.states, 5);
+        assert_eq!(rule.neighbor_mode, NeighborMode::VonNeumann);
+        assert_eq!(rule.radius, 2);
+    }
+
+    #[test]
+    fn multi_byte_parts_are_errors() {
+        // Smart quotes, as pasted from a forum post.
+        let e = parse_rule("\u{201c}B4/S4\u{201d}", RuleFormat::Auto).unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::UnknownPart);
+        assert_eq!(e.position, 0);
+        let e = parse_rule("B4/S4/\u{e9}5", RuleFormat::Bays).unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::UnknownPart);
+        assert_eq!(e.segment, "\u{e9}5");
+    }
+
+    #[test]
+    fn errors_point_at_the_segment() {
+        let e = parse_rule("4,9-26/4,18-24/5/N", RuleFormat::Slashed).unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::OutOfRange { value: 9, max: 6 });
+        assert_eq!(e.position, 2);
+        let e = parse_rule("4/4/5/M4", RuleFormat::Slashed).unwrap_err();
+        assert_eq!(e.kind, ParseErrorKind::InvalidRadius);
+        assert_eq!(e.position, 7);
+    }
+
+    #[test]
+    fn format_arg_names() {
+        for format in RuleFormat::ALL {
+            assert_eq!(RuleFormat::from_arg_name(format.arg_name()), Some(format));
+        }
+        assert_eq!(RuleFormat::from_arg_name("Bays"), Some(RuleFormat::Bays));
+        assert_eq!(RuleFormat::from_arg_name("life"), None);
+    }
+}
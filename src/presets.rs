@@ -0,0 +1,469 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+
+use crate::rule::{BoundaryMode, ColorMode, NeighborMode, NeighborWeights, Rule, SpawnMode, Value};
+
+/// Directory scanned for user presets at startup, relative to the working directory.
+pub const PRESET_DIR: &str = "presets";
+const PRESET_EXTENSION: &str = "ron";
+
+#[derive(Clone, Debug)]
+pub struct Preset {
+    pub name: String,
+    pub rule: Rule,
+}
+
+impl Preset {
+    /// Copies the automaton part of the preset into `rule`, leaving the grid
+    /// size alone. The recommended spawn and color mode are only applied if
+    /// `spawn_and_color` is set.
+    pub fn apply(&self, rule: &mut Rule, spawn_and_color: bool) {
+        rule.survival = self.rule.survival;
+        rule.birth = self.rule.birth;
+        rule.states = self.rule.states;
+        rule.neighbor_mode = self.rule.neighbor_mode;
+        rule.radius = self.rule.radius;
+        rule.weights = self.rule.weights;
+        rule.boundary_mode = self.rule.boundary_mode;
+        if spawn_and_color {
+            rule.spawn_mode = self.rule.spawn_mode.clone();
+            rule.color_mode = self.rule.color_mode.clone();
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct Presets(pub Vec<Preset>);
+
+impl Default for Presets {
+    fn default() -> Self {
+        let mut presets = builtin();
+        presets.extend(load_dir(PRESET_DIR));
+        Self(presets)
+    }
+}
+
+impl Presets {
+    /// Adds `preset`, replacing any existing preset with the same name.
+    pub fn insert(&mut self, preset: Preset) {
+        if let Some(existing) = self.0.iter_mut().find(|p| p.name == preset.name) {
+            *existing = preset;
+        } else {
+            self.0.push(preset);
+        }
+    }
+}
+
+/// Errors reading or writing preset and snapshot files.
+#[derive(Debug)]
+pub enum FileError {
+    Io(io::Error),
+    Serialize(ron::Error),
+    Deserialize(ron::error::SpannedError),
+    /// A file written by a newer version, with the version it was written by.
+    Version { found: u32, expected: u32 },
+    /// A file that is corrupt or wasn't written by this program.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Serialize(e) => write!(f, "{}", e),
+            Self::Deserialize(e) => write!(f, "{}", e),
+            Self::Version { found, expected } => write!(
+                f,
+                "version {} is not supported, expected {}",
+                found, expected
+            ),
+            Self::Invalid(e) => write!(f, "invalid file, {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ron::Error> for FileError {
+    fn from(e: ron::Error) -> Self {
+        Self::Serialize(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for FileError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        Self::Deserialize(e)
+    }
+}
+
+/// Path of the user preset file called `name`.
+pub fn preset_path(name: &str) -> PathBuf {
+    Path::new(PRESET_DIR).join(format!("{}.{}", name, PRESET_EXTENSION))
+}
+
+pub fn save_rule(rule: &Rule, path: impl AsRef<Path>) -> Result<(), FileError> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let s = ron::ser::to_string_pretty(
+        &rule.without_custom_cells(),
+        ron::ser::PrettyConfig::default(),
+    )?;
+    fs::write(path, s)?;
+    Ok(())
+}
+
+pub fn load_rule(path: impl AsRef<Path>) -> Result<Rule, FileError> {
+    let s = fs::read_to_string(path)?;
+    Ok(ron::from_str(&s)?)
+}
+
+/// Loads every preset file in `dir`, skipping (and logging) files that fail to parse.
+pub fn load_dir(dir: impl AsRef<Path>) -> Vec<Preset> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut presets: Vec<Preset> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |e| e == PRESET_EXTENSION))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            match load_rule(&path) {
+                Ok(rule) => Some(Preset { name, rule }),
+                Err(e) => {
+                    warn!("Failed to load preset {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+    presets.sort_by(|a, b| a.name.cmp(&b.name));
+    presets
+}
+
+/// Density of live cells for the random spawn, stored the way `SpawnMode::Random` wants it.
+fn random(density: f32) -> SpawnMode {
+    SpawnMode::Random(1.0 - density, 0)
+}
+
+fn lerp(a: (u8, u8, u8), b: (u8, u8, u8)) -> ColorMode {
+    ColorMode::StateLerp(Color::rgb_u8(a.0, a.1, a.2), Color::rgb_u8(b.0, b.1, b.2))
+}
+
+/// Rules are written as in the literature and `Rule::export`,
+/// `survival/birth/states/neighborhood`, where the state count includes the
+/// dead state. `Rule` counts only the alive state and its decay steps, hence
+/// `states - 1`.
+fn preset(
+    name: &str,
+    survival: &str,
+    birth: &str,
+    states: u32,
+    neighbor_mode: NeighborMode,
+    spawn_mode: SpawnMode,
+    color_mode: ColorMode,
+) -> Preset {
+    Preset {
+        name: name.to_string(),
+        rule: Rule {
+            size: UVec3::ZERO,
+            spawn_mode,
+            survival: Value::try_parse(survival).unwrap(),
+            birth: Value::try_parse(birth).unwrap(),
+            states: states - 1,
+            neighbor_mode,
+            radius: 1,
+            weights: NeighborWeights::Uniform,
+            boundary_mode: BoundaryMode::Dead,
+            color_mode,
+        },
+    }
+}
+
+pub fn builtin() -> Vec<Preset> {
+    use NeighborMode::{Moore, VonNeumann};
+    vec![
+        preset(
+            "445",
+            "4",
+            "4",
+            5,
+            Moore,
+            random(0.3),
+            lerp((94, 25, 146), (12, 227, 227)),
+        ),
+        preset(
+            "Amoeba",
+            "9-26",
+            "5-7,12,13,15",
+            5,
+            Moore,
+            random(0.3),
+            lerp((40, 10, 60), (230, 80, 160)),
+        ),
+        preset(
+            "Architecture",
+            "4-6",
+            "3",
+            2,
+            Moore,
+            random(0.05),
+            lerp((90, 90, 90), (240, 230, 210)),
+        ),
+        preset(
+            "Builder 1",
+            "2,6,9",
+            "4,6,8,9",
+            10,
+            Moore,
+            random(0.05),
+            lerp((20, 40, 120), (250, 200, 60)),
+        ),
+        preset(
+            "Builder 2",
+            "5-7",
+            "1",
+            2,
+            Moore,
+            random(0.01),
+            lerp((30, 90, 40), (200, 240, 120)),
+        ),
+        preset(
+            "Clouds 1",
+            "13-26",
+            "13,14,17-19",
+            2,
+            Moore,
+            random(0.5),
+            lerp((120, 140, 170), (250, 250, 255)),
+        ),
+        preset(
+            "Clouds 2",
+            "12-26",
+            "13,14",
+            2,
+            Moore,
+            random(0.5),
+            lerp((120, 140, 170), (250, 250, 255)),
+        ),
+        preset(
+            "Construction",
+            "0-2,4,6-11,13-17,21-26",
+            "9,10,16,23,24",
+            2,
+            Moore,
+            random(0.3),
+            lerp((60, 60, 70), (220, 140, 40)),
+        ),
+        preset(
+            "Coral",
+            "5-8",
+            "6,7,9,12",
+            4,
+            Moore,
+            random(0.3),
+            lerp((120, 20, 40), (255, 130, 100)),
+        ),
+        preset(
+            "Crystal Growth 1",
+            "0-6",
+            "1,3",
+            2,
+            VonNeumann,
+            random(0.001),
+            lerp((20, 60, 140), (160, 230, 255)),
+        ),
+        preset(
+            "Crystal Growth 2",
+            "1,2",
+            "1,3",
+            5,
+            VonNeumann,
+            random(0.001),
+            lerp((20, 60, 140), (160, 230, 255)),
+        ),
+        preset(
+            "Diamond Growth",
+            "5,6",
+            "1-3",
+            7,
+            VonNeumann,
+            random(0.001),
+            lerp((30, 30, 80), (200, 255, 255)),
+        ),
+        preset(
+            "Expanding Shell",
+            "6-9,11,13,15,16,18",
+            "6-10,13,14,16,18,19,22-25",
+            5,
+            Moore,
+            random(0.3),
+            lerp((70, 20, 20), (250, 180, 60)),
+        ),
+        preset(
+            "More Structures",
+            "7-26",
+            "4",
+            4,
+            Moore,
+            random(0.1),
+            lerp((40, 40, 100), (180, 220, 250)),
+        ),
+        preset(
+            "Pulse Waves",
+            "3",
+            "1-3",
+            10,
+            Moore,
+            random(0.01),
+            lerp((10, 20, 80), (90, 250, 220)),
+        ),
+        preset(
+            "Pyroclastic",
+            "4-7",
+            "6-8",
+            10,
+            Moore,
+            random(0.3),
+            lerp((60, 0, 0), (255, 200, 40)),
+        ),
+        preset(
+            "Sample 1",
+            "10-26",
+            "5,8-26",
+            4,
+            Moore,
+            random(0.3),
+            lerp((50, 20, 90), (240, 120, 200)),
+        ),
+        preset(
+            "Shells",
+            "3,5,7,9,11,15,17,19,21,23,24,26",
+            "3,6,8,9,11,14-17,19,24",
+            7,
+            Moore,
+            random(0.3),
+            lerp((30, 70, 90), (240, 220, 180)),
+        ),
+        preset(
+            "Slow Decay 1",
+            "13-26",
+            "10-26",
+            3,
+            Moore,
+            random(0.5),
+            lerp((40, 20, 60), (250, 150, 80)),
+        ),
+        preset(
+            "Slow Decay 2",
+            "1,4,8,11,13-26",
+            "13-26",
+            5,
+            Moore,
+            random(0.5),
+            lerp((40, 20, 60), (250, 150, 80)),
+        ),
+        preset(
+            "Spiky Growth",
+            "0-3,7-9,11-13,18,21,22,24,26",
+            "13,17,20-26",
+            4,
+            Moore,
+            random(0.3),
+            lerp((20, 80, 30), (240, 250, 120)),
+        ),
+        preset(
+            "Stable Structures",
+            "13-26",
+            "14-19",
+            2,
+            Moore,
+            random(0.5),
+            lerp((80, 80, 90), (200, 210, 230)),
+        ),
+        preset(
+            "Symmetry",
+            "",
+            "2",
+            10,
+            Moore,
+            SpawnMode::MengerSponge,
+            lerp((90, 10, 120), (250, 230, 90)),
+        ),
+        preset(
+            "Von Neumann Builder",
+            "1-3",
+            "1,4,5",
+            5,
+            VonNeumann,
+            random(0.01),
+            lerp((30, 50, 110), (240, 170, 70)),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::RuleFormat;
+
+    fn assert_file_round_trip(rule: &Rule) {
+        let path = std::env::temp_dir().join(format!(
+            "cas_preset_{}_{}.ron",
+            std::process::id(),
+            rule.export().replace('/', "_")
+        ));
+        save_rule(rule, &path).unwrap();
+        let loaded = load_rule(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), *rule);
+    }
+
+    #[test]
+    fn presets_match_their_rule_strings() {
+        let presets = builtin();
+        let find = |name: &str| &presets.iter().find(|p| p.name == name).unwrap().rule;
+        for (name, s) in [("445", "4/4/5/M"), ("Amoeba", "9-26/5-7,12-13,15/5/M")] {
+            let rule = find(name);
+            assert_eq!(rule.import(s, RuleFormat::Auto).unwrap(), *rule, "{}", s);
+        }
+    }
+
+    #[test]
+    fn files_round_trip_exactly() {
+        assert_file_round_trip(&Rule {
+            neighbor_mode: NeighborMode::Custom(0x20b411),
+            color_mode: ColorMode::StateLerp(
+                Color::rgba(0.1, 0.2, 0.3, 0.4),
+                Color::hsla(200.0, 0.5, 0.25, 1.0),
+            ),
+            ..Rule::default()
+        });
+        assert_file_round_trip(&Rule {
+            size: UVec3::new(512, 512, 16),
+            spawn_mode: SpawnMode::Random(0.7, 1234),
+            radius: 2,
+            weights: NeighborWeights::Classes {
+                face: 3,
+                edge: 2,
+                corner: 1,
+            },
+            boundary_mode: BoundaryMode::Wrap,
+            color_mode: ColorMode::DistToCenter(
+                Color::hsla(10.0, 0.9, 0.6, 0.5),
+                Color::rgba_linear(0.7, 0.1, 0.05, 1.0),
+            ),
+            ..Rule::default()
+        });
+    }
+}
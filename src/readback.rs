@@ -0,0 +1,266 @@
+use std::{
+    num::NonZeroU32,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::*,
+        renderer::RenderDevice,
+        texture::GpuImage,
+        RenderApp, RenderStage,
+    },
+};
+
+use crate::{cpu::CpuGrid, rule::CellFormat};
+
+/// Copies of the grid from the GPU, delivered as `GridReadback` events.
+pub struct ReadbackPlugin;
+
+impl Plugin for ReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let channel = ReadbackChannel::default();
+        app.init_resource::<RequestReadback>()
+            .insert_resource(channel.clone())
+            .add_event::<GridReadback>()
+            .add_system_to_stage(CoreStage::First, clear_request)
+            .add_system_to_stage(CoreStage::PreUpdate, receive_readbacks);
+        app.add_plugin(ExtractResourcePlugin::<RequestReadback>::default());
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .init_resource::<Readbacks>()
+            .insert_resource(channel)
+            .add_system_to_stage(RenderStage::Cleanup, map_readbacks);
+    }
+}
+
+/// Set to have the latest generation copied back to the CPU. Cleared at the
+/// start of every frame, so set it each frame a copy is wanted.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct RequestReadback(pub bool);
+
+/// The grid as it was at `generation`, arriving a few frames after it was
+/// requested. Shared, so readers can hold on to it without copying the cells.
+pub struct GridReadback {
+    pub generation: u64,
+    pub grid: Arc<PackedGrid>,
+}
+
+/// Cells read back from the GPU, in texture order and in the texture's
+/// format rather than widened to `u32`.
+#[derive(Debug)]
+pub struct PackedGrid {
+    size: UVec3,
+    format: CellFormat,
+    bytes: Vec<u8>,
+}
+
+impl PackedGrid {
+    /// Wraps `bytes` holding the cells of a `size` grid in texture order,
+    /// `format.pixel_size()` bytes each.
+    pub fn new(size: UVec3, format: CellFormat, bytes: Vec<u8>) -> Self {
+        assert_eq!(
+            bytes.len(),
+            size.x as usize * size.y as usize * size.z as usize * format.pixel_size()
+        );
+        Self {
+            size,
+            format,
+            bytes,
+        }
+    }
+
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    pub fn format(&self) -> CellFormat {
+        self.format
+    }
+
+    /// The cells as stored in the texture, `format.pixel_size()` bytes each.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The state of every cell, in texture order.
+    pub fn cells(&self) -> impl Iterator<Item = u32> + '_ {
+        self.bytes
+            .chunks_exact(self.format.pixel_size())
+            .map(|cell| self.format.decode(cell))
+    }
+
+    /// Widens the cells into a `CpuGrid`, e.g. to save them.
+    pub fn to_cpu_grid(&self) -> CpuGrid {
+        CpuGrid::from_cells(self.size, self.cells().collect())
+    }
+}
+
+/// Readbacks the render world has finished, waiting to be sent as events.
+#[derive(Resource, Clone, Default)]
+struct ReadbackChannel(Arc<Mutex<Vec<GridReadback>>>);
+
+/// Copies no longer requested while this many are still on their way, so
+/// requesting every frame doesn't pile up buffers when the GPU is behind.
+const MAX_IN_FLIGHT: usize = 3;
+
+/// Copies in flight in the render world and the buffers they can reuse.
+/// Behind a mutex since `DispatchCA` adds to it with only shared access to
+/// the world.
+#[derive(Resource, Default)]
+pub struct Readbacks(Mutex<ReadbackBuffers>);
+
+#[derive(Default)]
+struct ReadbackBuffers {
+    pending: Vec<PendingReadback>,
+    /// Unmapped buffers of finished copies, reused while the grid keeps its
+    /// size and format.
+    free: Vec<FreeBuffer>,
+}
+
+struct FreeBuffer {
+    buffer: Buffer,
+    format: CellFormat,
+    size: UVec3,
+}
+
+struct PendingReadback {
+    buffer: Buffer,
+    format: CellFormat,
+    size: UVec3,
+    padded_bytes_per_row: usize,
+    generation: u64,
+    /// Set once the buffer is being mapped, to whether mapping it succeeded
+    /// once it has.
+    mapped: Option<Arc<Mutex<Option<bool>>>>,
+}
+
+impl Readbacks {
+    /// Encodes a copy of `image`, which holds `generation`, if one was
+    /// requested this frame.
+    pub fn copy(
+        &self,
+        world: &World,
+        encoder: &mut CommandEncoder,
+        image: &GpuImage,
+        format: CellFormat,
+        size: UVec3,
+        generation: u64,
+    ) {
+        if !matches!(world.get_resource(), Some(RequestReadback(true))) {
+            return;
+        }
+        let mut buffers = self.0.lock().unwrap();
+        if buffers.pending.len() >= MAX_IN_FLIGHT {
+            return;
+        }
+
+        // Buffers for another size or format won't fit again, so free them.
+        buffers
+            .free
+            .retain(|free| free.format == format && free.size == size);
+        let padded_bytes_per_row =
+            RenderDevice::align_copy_bytes_per_row(size.x as usize * format.pixel_size());
+        let buffer = match buffers.free.pop() {
+            Some(free) => free.buffer,
+            None => world
+                .resource::<RenderDevice>()
+                .create_buffer(&BufferDescriptor {
+                    label: Some("grid readback"),
+                    size: (padded_bytes_per_row * size.y as usize * size.z as usize) as u64,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+        };
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &image.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_bytes_per_row as u32),
+                    rows_per_image: NonZeroU32::new(size.y),
+                },
+            },
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+        );
+        buffers.pending.push(PendingReadback {
+            buffer,
+            format,
+            size,
+            padded_bytes_per_row,
+            generation,
+            mapped: None,
+        });
+    }
+}
+
+/// Runs after the frame's commands are submitted, which is when new copies can
+/// be mapped. Mapping finishes during a later submit, after which the cells
+/// are unpacked and handed to the main world.
+fn map_readbacks(readbacks: Res<Readbacks>, channel: Res<ReadbackChannel>) {
+    let mut buffers = readbacks.0.lock().unwrap();
+    let ReadbackBuffers { pending, free } = &mut *buffers;
+    pending.retain_mut(|readback| {
+        let Some(mapped) = &readback.mapped else {
+            let mapped = Arc::new(Mutex::new(None));
+            let result = mapped.clone();
+            readback
+                .buffer
+                .slice(..)
+                .map_async(MapMode::Read, move |res| {
+                    *result.lock().unwrap() = Some(res.is_ok())
+                });
+            readback.mapped = Some(mapped);
+            return true;
+        };
+        match *mapped.lock().unwrap() {
+            None => return true,
+            Some(false) => {
+                error!("Failed to map grid readback");
+                return false;
+            }
+            Some(true) => {}
+        }
+
+        let size = readback.size;
+        let row_bytes = size.x as usize * readback.format.pixel_size();
+        let mut bytes = Vec::with_capacity(row_bytes * size.y as usize * size.z as usize);
+        {
+            let data = readback.buffer.slice(..).get_mapped_range();
+            for row in data.chunks_exact(readback.padded_bytes_per_row) {
+                bytes.extend_from_slice(&row[..row_bytes]);
+            }
+        }
+        readback.buffer.unmap();
+        free.push(FreeBuffer {
+            buffer: readback.buffer.clone(),
+            format: readback.format,
+            size,
+        });
+        channel.0.lock().unwrap().push(GridReadback {
+            generation: readback.generation,
+            grid: Arc::new(PackedGrid::new(size, readback.format, bytes)),
+        });
+        false
+    });
+}
+
+fn clear_request(mut request: ResMut<RequestReadback>) {
+    request.0 = false;
+}
+
+fn receive_readbacks(channel: Res<ReadbackChannel>, mut events: EventWriter<GridReadback>) {
+    events.send_batch(channel.0.lock().unwrap().drain(..));
+}
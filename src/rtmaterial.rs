@@ -7,7 +7,7 @@ use crate::rule::{GPURule, Rule};
 #[derive(Debug, Clone, TypeUuid)]
 #[uuid = "1ae9c363-1234-4213-890e-192d81b00281"]
 pub struct RTVolumeMaterial {
-    pub volume: Option<Handle<Image>>,
+    pub volumes: Option<[Handle<Image>; 2]>,
     pub rule: Rule,
 }
 
@@ -21,12 +21,15 @@ impl AsBindGroup for RTVolumeMaterial {
         images: &bevy::render::render_asset::RenderAssets<Image>,
         _fallback_image: &bevy::render::texture::FallbackImage,
     ) -> Result<PreparedBindGroup<Self>, AsBindGroupError> {
-        let volume = self
-            .volume
+        let volumes = self
+            .volumes
             .as_ref()
             .ok_or(AsBindGroupError::RetryNextUpdate)?;
-        let image = images
-            .get(volume)
+        let image_a = images
+            .get(&volumes[0])
+            .ok_or(AsBindGroupError::RetryNextUpdate)?;
+        let image_b = images
+            .get(&volumes[1])
             .ok_or(AsBindGroupError::RetryNextUpdate)?;
 
         let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
@@ -35,23 +38,41 @@ impl AsBindGroup for RTVolumeMaterial {
             usage: BufferUsages::UNIFORM,
         });
 
+        // Which of the two volumes holds the latest generation, written by `DispatchCA`.
+        let front_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("front_buffer"),
+            contents: bytemuck::bytes_of(&0u32),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
         let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
             label: None,
             layout,
             entries: &[
                 BindGroupEntry {
                     binding: 0,
-                    resource: BindingResource::TextureView(&image.texture_view),
+                    resource: BindingResource::TextureView(&image_a.texture_view),
                 },
                 BindGroupEntry {
                     binding: 1,
                     resource: buffer.as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&image_b.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: front_buffer.as_entire_binding(),
+                },
             ],
         });
 
         Ok(PreparedBindGroup {
-            bindings: vec![OwnedBindingResource::Buffer(buffer)],
+            bindings: vec![
+                OwnedBindingResource::Buffer(buffer),
+                OwnedBindingResource::Buffer(front_buffer),
+            ],
             bind_group,
             data: (),
         })
@@ -63,21 +84,43 @@ impl AsBindGroup for RTVolumeMaterial {
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
-                    ty: BindingType::StorageTexture {
-                        access: StorageTextureAccess::ReadWrite,
-                        format: TextureFormat::R8Uint,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Uint,
                         view_dimension: TextureViewDimension::D3,
+                        multisampled: false,
                     },
                     count: None,
                 },
                 BindGroupLayoutEntry {
                     binding: 1,
-                    visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            NonZeroU64::new(std::mem::size_of::<GPURule>() as u64).unwrap(),
+                        ),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Uint,
+                        view_dimension: TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
-                        min_binding_size: Some(NonZeroU64::new(64).unwrap()),
+                        min_binding_size: Some(NonZeroU64::new(4).unwrap()),
                     },
                     count: None,
                 },
@@ -1,41 +1,65 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use bevy::{
     prelude::*,
-    render::extract_resource::{ExtractResource, ExtractResourcePlugin},
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::TextureFormat,
+    },
 };
 use bytemuck::{Pod, Zeroable};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::rtmaterial::RTVolumeMaterial;
+use crate::{
+    cpu::{hash, CpuGrid},
+    parse::{parse_rule, parse_value, ParseError, RuleFormat},
+    presets::Presets,
+    rtmaterial::RTVolumeMaterial,
+};
 
+/// A set of neighbour counts, one bit per count.
 #[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
-pub struct Value([bool; 27]);
+pub struct Value([u32; Value::WORDS]);
 
 impl Value {
-    pub fn at(&self, index: usize) -> &bool {
-        &self.0[index]
+    /// Enough words for every count of a radius 3 Moore neighborhood.
+    const WORDS: usize = 11;
+    /// The largest count a `Value` can hold.
+    pub const MAX_COUNT: usize = Self::WORDS * 32 - 1;
+
+    pub fn get(&self, count: usize) -> bool {
+        count <= Self::MAX_COUNT && (self.0[count / 32] >> (count % 32)) & 1 != 0
     }
 
-    pub fn at_mut(&mut self, index: usize) -> &mut bool {
-        &mut self.0[index]
+    pub fn set(&mut self, count: usize, value: bool) {
+        if value {
+            self.0[count / 32] |= 1 << (count % 32);
+        } else {
+            self.0[count / 32] &= !(1 << (count % 32));
+        }
+    }
+
+    /// Clears every count above `max`, e.g. after the neighborhood shrank.
+    pub fn clear_above(&mut self, max: usize) {
+        for count in max + 1..=Self::MAX_COUNT {
+            self.set(count, false);
+        }
     }
 
     pub fn try_parse(s: &str) -> Option<Value> {
-        if s.len() == 0 {
-            return Some(Value::default());
-        }
-        let mut res = Value::default();
-        for value in s.split(",") {
-            let value = value.trim();
-            if let Some((r0, r1)) = value.split_once('-') {
-                let r0 = r0.trim_end();
-                let r1 = r1.trim_start();
-                for i in r0.parse::<usize>().ok()?..=r1.parse::<usize>().ok()? {
-                    *res.0.get_mut(26 - i)? = true;
-                }
-            } else {
-                *res.0.get_mut(26 - value.parse::<usize>().ok()?)? = true;
-            }
+        parse_value(s, 0, Self::MAX_COUNT).ok()
+    }
+
+    /// The bits packed into the `array<vec4<u32>, 3>` the shaders expect.
+    pub fn to_gpu(&self) -> [[u32; 4]; 3] {
+        let mut res = [[0; 4]; 3];
+        for (i, word) in self.0.iter().enumerate() {
+            res[i / 4][i % 4] = *word;
         }
-        Some(res)
+        res
     }
 }
 
@@ -43,13 +67,13 @@ impl ToString for Value {
     fn to_string(&self) -> String {
         let mut elems = Vec::new();
         let mut i = 0;
-        while i < 27 {
-            if *self.at(i) {
-                let end = 26 - i;
-                while i < 27 && *self.at(i) {
+        while i <= Self::MAX_COUNT {
+            if self.get(i) {
+                let start = i;
+                while i <= Self::MAX_COUNT && self.get(i) {
                     i += 1;
                 }
-                let start = 26 - (i - 1);
+                let end = i - 1;
                 if start == end {
                     elems.push(start.to_string());
                 } else if start + 1 == end {
@@ -61,50 +85,328 @@ impl ToString for Value {
                 i += 1;
             }
         }
-        if elems.is_empty() {
-            String::new()
-        } else {
-            elems
-                .iter()
-                //.rev()
-                .skip(1)
-                .fold(elems[0].clone(), |acc, x| format!("{},{}", x, acc))
-        }
+        elems.join(",")
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Value::try_parse(&s).ok_or_else(|| de::Error::custom(format!("invalid value `{}`", s)))
     }
 }
 
 impl From<Vec<usize>> for Value {
     fn from(vec: Vec<usize>) -> Self {
-        let mut value = [false; 27];
+        let mut value = Value::default();
         for i in vec {
-            value[26 - i] = true;
+            value.set(i, true);
         }
-        Self(value)
+        value
     }
 }
 
-impl From<Value> for u32 {
-    fn from(v: Value) -> Self {
-        v.0.iter().fold(0, |acc, &b| (acc << 1) | (b as u32))
-    }
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum NeighborMode {
+    Moore,
+    VonNeumann,
+    /// Any subset of the 26 cells around the center, one bit per offset as
+    /// given by `NeighborMode::mask_bit`. Always has a radius of 1.
+    Custom(u32),
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum NeighborMode {
-    Moore = 0,
-    VonNeumann = 1,
+pub enum NeighborModeKind {
+    Moore,
+    VonNeumann,
+    Custom,
 }
 
-impl NeighborMode {
+impl NeighborModeKind {
+    pub const ALL: [NeighborModeKind; 3] = [Self::Moore, Self::VonNeumann, Self::Custom];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::Moore => "Moore",
             Self::VonNeumann => "VonNeumann",
+            Self::Custom => "Custom",
+        }
+    }
+
+    /// Converts `mode` to this kind. Switching to `Custom` starts from the
+    /// radius 1 version of the current neighborhood.
+    pub fn update(&self, mode: &mut NeighborMode) {
+        *mode = match self {
+            Self::Moore => NeighborMode::Moore,
+            Self::VonNeumann => NeighborMode::VonNeumann,
+            Self::Custom => NeighborMode::Custom(mode.mask()),
+        }
+    }
+}
+
+impl NeighborMode {
+    /// Mask with every bit of a custom neighborhood set.
+    pub const FULL_MASK: u32 = (1 << 26) - 1;
+
+    pub fn kind(&self) -> NeighborModeKind {
+        match self {
+            Self::Moore => NeighborModeKind::Moore,
+            Self::VonNeumann => NeighborModeKind::VonNeumann,
+            Self::Custom(_) => NeighborModeKind::Custom,
+        }
+    }
+
+    /// The neighborhood in rule strings, a letter or `X` followed by the
+    /// mask of a custom neighborhood in hex.
+    pub fn short_name(&self) -> String {
+        match self {
+            Self::Moore => "M".to_string(),
+            Self::VonNeumann => "N".to_string(),
+            Self::Custom(mask) => format!("X{:07x}", mask),
+        }
+    }
+
+    /// The bit of a custom mask used for `offset`, or `None` for the center
+    /// and cells further away than 1. Bits go x first, then y, then z,
+    /// skipping the center.
+    pub fn mask_bit(offset: [i32; 3]) -> Option<u32> {
+        let [x, y, z] = offset;
+        if offset == [0, 0, 0] || x.abs() > 1 || y.abs() > 1 || z.abs() > 1 {
+            return None;
+        }
+        let index = ((z + 1) * 9 + (y + 1) * 3 + x + 1) as u32;
+        Some(if index > 13 { index - 1 } else { index })
+    }
+
+    /// The radius 1 cells of this neighborhood as a custom mask.
+    pub fn mask(&self) -> u32 {
+        if let Self::Custom(mask) = self {
+            return *mask;
+        }
+        let mut mask = 0;
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let offset = [x, y, z];
+                    if let Some(bit) = Self::mask_bit(offset) {
+                        if self.contains(offset, 1) {
+                            mask |= 1 << bit;
+                        }
+                    }
+                }
+            }
+        }
+        mask
+    }
+
+    /// The largest number of alive neighbours a cell can have.
+    pub fn max_neighbors(&self, radius: u32) -> usize {
+        let d = 2 * radius as usize + 1;
+        match self {
+            Self::Moore => d * d * d - 1,
+            Self::VonNeumann => {
+                let r = radius as usize;
+                d * (2 * r * r + 2 * r + 3) / 3 - 1
+            }
+            Self::Custom(mask) => mask.count_ones() as usize,
+        }
+    }
+
+    /// Whether the cell at `offset` is a neighbour, the center cell never is.
+    pub fn contains(&self, offset: [i32; 3], radius: u32) -> bool {
+        let radius = radius as i32;
+        let [x, y, z] = offset;
+        if offset == [0, 0, 0] {
+            return false;
+        }
+        match self {
+            Self::Moore => x.abs() <= radius && y.abs() <= radius && z.abs() <= radius,
+            Self::VonNeumann => x.abs() + y.abs() + z.abs() <= radius,
+            Self::Custom(mask) => {
+                Self::mask_bit(offset).map_or(false, |bit| mask & (1 << bit) != 0)
+            }
+        }
+    }
+}
+
+/// How much each neighbour adds to the count that survival and birth are
+/// checked against.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum NeighborWeights {
+    /// Every neighbour counts as 1.
+    #[default]
+    Uniform,
+    /// Neighbours sharing a face, an edge or only a corner with the center,
+    /// going by how many axes their offset is nonzero on.
+    Classes { face: u8, edge: u8, corner: u8 },
+    /// One weight per radius 1 offset, in `NeighborMode::mask_bit` order.
+    /// Cells further away weigh nothing.
+    Offsets([u8; 26]),
+}
+
+impl NeighborWeights {
+    pub fn kind(&self) -> NeighborWeightsKind {
+        match self {
+            Self::Uniform => NeighborWeightsKind::Uniform,
+            Self::Classes { .. } => NeighborWeightsKind::Classes,
+            Self::Offsets(_) => NeighborWeightsKind::Offsets,
+        }
+    }
+
+    pub fn weight(&self, offset: [i32; 3]) -> u32 {
+        match self {
+            Self::Uniform => 1,
+            Self::Classes { face, edge, corner } => {
+                match offset.iter().filter(|&&v| v != 0).count() {
+                    1 => *face as u32,
+                    2 => *edge as u32,
+                    _ => *corner as u32,
+                }
+            }
+            Self::Offsets(weights) => {
+                NeighborMode::mask_bit(offset).map_or(0, |bit| weights[bit as usize] as u32)
+            }
+        }
+    }
+
+    /// The largest weighted count a cell can have, capped to what a `Value` holds.
+    pub fn max_count(&self, mode: NeighborMode, radius: u32) -> usize {
+        if *self == Self::Uniform {
+            return mode.max_neighbors(radius).min(Value::MAX_COUNT);
+        }
+        let r = radius as i32;
+        let mut count = 0;
+        for x in -r..=r {
+            for y in -r..=r {
+                for z in -r..=r {
+                    if mode.contains([x, y, z], radius) {
+                        count += self.weight([x, y, z]) as usize;
+                    }
+                }
+            }
+        }
+        count.min(Value::MAX_COUNT)
+    }
+
+    /// The weights as a `W` part of a rule string, e.g. `W2,1,1`, or `None`
+    /// if they are uniform.
+    pub fn short_name(&self) -> Option<String> {
+        let weights: Vec<String> = match self {
+            Self::Uniform => return None,
+            Self::Classes { face, edge, corner } => {
+                vec![face.to_string(), edge.to_string(), corner.to_string()]
+            }
+            Self::Offsets(weights) => weights.iter().map(u8::to_string).collect(),
+        };
+        Some(format!("W{}", weights.join(",")))
+    }
+
+    /// The weights packed into the `array<vec4<u32>, 2>` the shaders expect.
+    /// Classes are stored as the first three words and offsets as one byte each.
+    pub fn to_gpu(&self) -> [[u32; 4]; 2] {
+        let mut res = [[0; 4]; 2];
+        match self {
+            Self::Uniform => {}
+            Self::Classes { face, edge, corner } => {
+                res[0] = [*face as u32, *edge as u32, *corner as u32, 0];
+            }
+            Self::Offsets(weights) => {
+                for (i, weight) in weights.iter().enumerate() {
+                    res[i / 16][(i / 4) % 4] |= (*weight as u32) << (i % 4 * 8);
+                }
+            }
+        }
+        res
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NeighborWeightsKind {
+    Uniform,
+    Classes,
+    Offsets,
+}
+
+impl NeighborWeightsKind {
+    pub const ALL: [NeighborWeightsKind; 3] = [Self::Uniform, Self::Classes, Self::Offsets];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Uniform => "Uniform",
+            Self::Classes => "Face/Edge/Corner",
+            Self::Offsets => "Per Offset",
+        }
+    }
+
+    /// Converts `weights` to this kind, keeping the weight of every radius 1
+    /// offset where possible.
+    pub fn update(&self, weights: &mut NeighborWeights) {
+        if weights.kind() == *self {
+            return;
+        }
+        *weights = match self {
+            Self::Uniform => NeighborWeights::Uniform,
+            Self::Classes => NeighborWeights::Classes {
+                face: weights.weight([1, 0, 0]) as u8,
+                edge: weights.weight([1, 1, 0]) as u8,
+                corner: weights.weight([1, 1, 1]) as u8,
+            },
+            Self::Offsets => {
+                let mut offsets = [0; 26];
+                for x in -1..=1 {
+                    for y in -1..=1 {
+                        for z in -1..=1 {
+                            if let Some(bit) = NeighborMode::mask_bit([x, y, z]) {
+                                offsets[bit as usize] = weights.weight([x, y, z]) as u8;
+                            }
+                        }
+                    }
+                }
+                NeighborWeights::Offsets(offsets)
+            }
+        }
+    }
+}
+
+/// The largest neighborhood radius, limited by how many counts a `Value` holds.
+pub const MAX_RADIUS: u32 = 3;
+
+/// The most cells the grid can have along any axis.
+pub const MAX_EXTENT: u32 = 1024;
+
+fn default_radius() -> u32 {
+    1
+}
+
+/// What cells outside the grid look like to their neighbours.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum BoundaryMode {
+    Dead = 0,
+    Alive = 1,
+    Wrap = 2,
+    Mirror = 3,
+}
+
+impl BoundaryMode {
+    pub const ALL: [BoundaryMode; 4] = [Self::Dead, Self::Alive, Self::Wrap, Self::Mirror];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dead => "Dead",
+            Self::Alive => "Alive",
+            Self::Wrap => "Wrap",
+            Self::Mirror => "Mirror",
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ColorMode {
     Single(Color),
     StateLerp(Color, Color),
@@ -171,10 +473,14 @@ impl ColorModeKind {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum SpawnMode {
-    Random(f32),
+    /// Spawn chance and the seed mixed into the per cell hash.
+    Random(f32, u32),
     MengerSponge,
+    /// Cells uploaded as they are on Reset. Cells stay dead if the grid isn't
+    /// the size of the rule.
+    Custom(CustomCells),
 }
 
 impl Eq for SpawnMode {}
@@ -182,36 +488,104 @@ impl Eq for SpawnMode {}
 impl SpawnMode {
     pub fn kind(&self) -> SpawnModeKind {
         match self {
-            Self::Random(_) => SpawnModeKind::Random,
+            Self::Random(_, _) => SpawnModeKind::Random,
             Self::MengerSponge => SpawnModeKind::MengerSponge,
+            Self::Custom(_) => SpawnModeKind::Custom,
         }
     }
 
     pub fn float(&self) -> f32 {
         match self {
-            Self::Random(f) => *f,
-            Self::MengerSponge => 0.0,
+            Self::Random(f, _) => *f,
+            Self::MengerSponge | Self::Custom(_) => 0.0,
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        match self {
+            Self::Random(_, seed) => *seed,
+            Self::MengerSponge | Self::Custom(_) => 0,
+        }
+    }
+}
+
+/// The initial cells of `SpawnMode::Custom`. Shared, since the rule is cloned
+/// into the render world every frame.
+#[derive(Clone, Debug, Eq)]
+pub struct CustomCells {
+    pub grid: Arc<CpuGrid>,
+    /// The generation the cells are at, which the counter starts from once
+    /// they're uploaded. Nonzero for cells from a snapshot.
+    pub generation: u64,
+}
+
+impl CustomCells {
+    pub fn new(grid: CpuGrid, generation: u64) -> Self {
+        Self {
+            grid: Arc::new(grid),
+            generation,
+        }
+    }
+}
+
+impl PartialEq for CustomCells {
+    fn eq(&self, other: &Self) -> bool {
+        self.generation == other.generation
+            && (Arc::ptr_eq(&self.grid, &other.grid) || self.grid == other.grid)
+    }
+}
+
+impl Serialize for CustomCells {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let size = self.grid.size();
+        ((size.x, size.y, size.z), self.generation, self.grid.cells()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CustomCells {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let ((x, y, z), generation, cells) =
+            <((u32, u32, u32), u64, Vec<u32>)>::deserialize(deserializer)?;
+        let size = UVec3::new(x, y, z);
+        if cells.len() as u64 != size.x as u64 * size.y as u64 * size.z as u64 {
+            return Err(de::Error::invalid_length(
+                cells.len(),
+                &"one cell per position of the grid",
+            ));
         }
+        Ok(Self::new(CpuGrid::from_cells(size, cells), generation))
     }
 }
 
+/// A seed that differs between calls, for rolling a new random soup.
+pub fn random_seed() -> u32 {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hash(time.subsec_nanos() ^ time.as_secs() as u32)
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum SpawnModeKind {
     Random,
     MengerSponge,
+    Custom,
 }
 
 impl SpawnModeKind {
+    /// Switching to `Custom` does nothing, since there are no cells to switch to.
     pub fn update(&self, mode: &mut SpawnMode) {
         let float = mode.float();
+        let seed = mode.seed();
         match self {
             Self::Random => {
-                *mode = SpawnMode::Random(float);
+                *mode = SpawnMode::Random(float, seed);
             }
             Self::MengerSponge => {
                 *mode = SpawnMode::MengerSponge;
             }
+            Self::Custom => {}
         }
     }
 
@@ -219,22 +593,112 @@ impl SpawnModeKind {
         match self {
             Self::Random => "Random",
             Self::MengerSponge => "Menger Sponge",
+            Self::Custom => "Custom",
+        }
+    }
+}
+/// The texture format cells are stored in, the narrowest one that holds
+/// every state of the rule.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CellFormat {
+    R8,
+    R16,
+    R32,
+}
+
+impl CellFormat {
+    pub const ALL: [CellFormat; 3] = [Self::R8, Self::R16, Self::R32];
+
+    pub fn for_states(states: u32) -> Self {
+        if states <= u8::MAX as u32 {
+            Self::R8
+        } else if states <= u16::MAX as u32 {
+            Self::R16
+        } else {
+            Self::R32
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::R8 => "R8",
+            Self::R16 => "R16",
+            Self::R32 => "R32",
+        }
+    }
+
+    pub fn texture_format(&self) -> TextureFormat {
+        match self {
+            Self::R8 => TextureFormat::R8Uint,
+            Self::R16 => TextureFormat::R16Uint,
+            Self::R32 => TextureFormat::R32Uint,
+        }
+    }
+
+    pub fn from_texture_format(format: TextureFormat) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|cell_format| cell_format.texture_format() == format)
+    }
+
+    /// Bytes per cell.
+    pub fn pixel_size(&self) -> usize {
+        match self {
+            Self::R8 => 1,
+            Self::R16 => 2,
+            Self::R32 => 4,
+        }
+    }
+
+    /// Appends one cell in the texture's bytes, saturating states the format
+    /// can't hold.
+    pub fn encode(&self, value: u32, bytes: &mut Vec<u8>) {
+        match self {
+            Self::R8 => bytes.push(value.min(u8::MAX as u32) as u8),
+            Self::R16 => bytes.extend((value.min(u16::MAX as u32) as u16).to_le_bytes()),
+            Self::R32 => bytes.extend(value.to_le_bytes()),
+        }
+    }
+
+    /// Reads one cell from the texture's bytes.
+    pub fn decode(&self, bytes: &[u8]) -> u32 {
+        match self {
+            Self::R8 => bytes[0] as u32,
+            Self::R16 => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            Self::R32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+
+    /// The shader def `compute.wgsl` uses to pick the format of the volume it writes.
+    pub fn shader_def(&self) -> &'static str {
+        match self {
+            Self::R8 => "CELLS_R8",
+            Self::R16 => "CELLS_R16",
+            Self::R32 => "CELLS_R32",
         }
     }
 }
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Default, Pod, Zeroable)]
 pub struct GPURule {
-    size: u32,
+    size: [u32; 3],
     spawn_mode: u32,
     spawn_chance: f32,
-    survival: u32,
-    birth: u32,
     states: u32,
     neighbor_mode: u32,
     color_mode: u32,
+    boundary_mode: u32,
+    seed: u32,
+    radius: u32,
+    neighbor_mask: u32,
+    weight_mode: u32,
+    _padding: [u32; 3],
     color0: [f32; 4],
     color1: [f32; 4],
+    survival: [[u32; 4]; 3],
+    birth: [[u32; 4]; 3],
+    weights: [[u32; 4]; 2],
 }
 
 impl From<&Rule> for GPURule {
@@ -245,32 +709,165 @@ impl From<&Rule> for GPURule {
             ColorMode::DistToCenter(c0, c1) => (2, c0.as_rgba_f32(), c1.as_rgba_f32()),
             ColorMode::Neighbour(c0, c1) => (3, c0.as_rgba_f32(), c1.as_rgba_f32()),
         };
+        let (neighbor_mode, neighbor_mask) = match rule.neighbor_mode {
+            NeighborMode::Moore => (0, 0),
+            NeighborMode::VonNeumann => (1, 0),
+            NeighborMode::Custom(mask) => (2, mask),
+        };
         Self {
-            size: rule.size,
+            size: rule.size.to_array(),
             spawn_mode: rule.spawn_mode.kind() as u32,
             spawn_chance: rule.spawn_mode.float(),
-            survival: rule.survival.into(),
-            birth: rule.birth.into(),
             states: rule.states,
-            neighbor_mode: rule.neighbor_mode as u32,
+            neighbor_mode,
             color_mode,
+            boundary_mode: rule.boundary_mode as u32,
+            seed: rule.spawn_mode.seed(),
+            radius: rule.radius,
+            neighbor_mask,
+            weight_mode: rule.weights.kind() as u32,
+            _padding: [0; 3],
             color0,
             color1,
+            survival: rule.survival.to_gpu(),
+            birth: rule.birth.to_gpu(),
+            weights: rule.weights.to_gpu(),
         }
     }
 }
 
-#[derive(Clone, Resource, PartialEq, Eq, Debug, ExtractResource)]
+fn serialize_size<S: Serializer>(size: &UVec3, serializer: S) -> Result<S::Ok, S::Error> {
+    (size.x, size.y, size.z).serialize(serializer)
+}
+
+/// Reads `(x, y, z)`, or a single number for the cubic grids older presets have.
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<UVec3, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Cube(u32),
+        Extents(u32, u32, u32),
+    }
+    Ok(match Size::deserialize(deserializer)? {
+        Size::Cube(size) => UVec3::splat(size),
+        Size::Extents(x, y, z) => UVec3::new(x, y, z),
+    })
+}
+
+#[derive(Clone, Resource, PartialEq, Eq, Debug, ExtractResource, Serialize, Deserialize)]
 pub struct Rule {
-    pub size: u32,
+    /// Cells along x, y and z.
+    #[serde(
+        serialize_with = "serialize_size",
+        deserialize_with = "deserialize_size"
+    )]
+    pub size: UVec3,
     pub spawn_mode: SpawnMode,
     pub survival: Value,
     pub birth: Value,
     pub states: u32,
     pub neighbor_mode: NeighborMode,
+    #[serde(default = "default_radius")]
+    pub radius: u32,
+    #[serde(default)]
+    pub weights: NeighborWeights,
+    pub boundary_mode: BoundaryMode,
     pub color_mode: ColorMode,
 }
 
+/// The rule the app starts with, a Menger sponge filling a 729³ grid.
+impl Default for Rule {
+    fn default() -> Self {
+        Rule {
+            size: UVec3::splat(243 * 3),
+            spawn_mode: SpawnMode::MengerSponge,
+            survival: vec![
+                4, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26,
+            ]
+            .into(),
+            birth: vec![4, 18, 19, 20, 21, 22, 23, 24].into(),
+            states: 5,
+            neighbor_mode: NeighborMode::Moore,
+            radius: 1,
+            weights: NeighborWeights::Uniform,
+            boundary_mode: BoundaryMode::Dead,
+            color_mode: ColorMode::StateLerp(
+                Color::rgb_u8(94, 25, 146),
+                Color::rgb_u8(12, 227, 227),
+            ),
+        }
+    }
+}
+
+impl Rule {
+    /// The rule in the `survival/birth/states/neighborhood` notation accepted
+    /// by `Rule::import`, e.g. `9-26/5-7,12,13,15/5/M`, where the states
+    /// include the dead state as in the literature. Radii other
+    /// than 1 are appended to the neighborhood, e.g. `M2`, and custom
+    /// neighborhoods are written as `X` and their mask, e.g. `X3ffffff`.
+    /// Weights other than uniform follow as a fifth part, e.g. `/W2,1,1`.
+    pub fn export(&self) -> String {
+        let radius = if self.radius == 1 || matches!(self.neighbor_mode, NeighborMode::Custom(_)) {
+            String::new()
+        } else {
+            self.radius.to_string()
+        };
+        let weights = self
+            .weights
+            .short_name()
+            .map_or(String::new(), |weights| format!("/{}", weights));
+        format!(
+            "{}/{}/{}/{}{}{}",
+            self.survival.to_string(),
+            self.birth.to_string(),
+            self.states + 1,
+            self.neighbor_mode.short_name(),
+            radius,
+            weights
+        )
+    }
+
+    /// Returns a copy of this rule with the fields covered by rule strings
+    /// replaced by those in `s`, written in `format`.
+    pub fn import(&self, s: &str, format: RuleFormat) -> Result<Rule, ParseError> {
+        let parsed = parse_rule(s, format)?;
+        Ok(Rule {
+            survival: parsed.survival,
+            birth: parsed.birth,
+            states: parsed.states,
+            neighbor_mode: parsed.neighbor_mode,
+            radius: parsed.radius,
+            weights: parsed.weights,
+            ..self.clone()
+        })
+    }
+
+    /// A copy to save in files. Custom cells are as large as the grid and
+    /// only saved by snapshots, so they're replaced by the Menger sponge.
+    pub fn without_custom_cells(&self) -> Rule {
+        let mut rule = self.clone();
+        if let SpawnMode::Custom(_) = rule.spawn_mode {
+            rule.spawn_mode = SpawnMode::MengerSponge;
+        }
+        rule
+    }
+
+    /// The extents of the grid relative to its longest side, used to scale
+    /// the mesh the grid is drawn in so cells stay cubic.
+    pub fn aspect(&self) -> Vec3 {
+        self.size.as_vec3() / self.size.max_element() as f32
+    }
+
+    pub fn cell_format(&self) -> CellFormat {
+        CellFormat::for_states(self.states)
+    }
+
+    /// The largest weighted count of alive neighbours a cell can have under this rule.
+    pub fn max_neighbors(&self) -> usize {
+        self.weights.max_count(self.neighbor_mode, self.radius)
+    }
+}
+
 fn update_materials(
     rule: Res<Rule>,
     material_query: Query<&Handle<RTVolumeMaterial>>,
@@ -289,16 +886,119 @@ pub struct RulePlugin;
 
 impl Plugin for RulePlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Rule {
-            size: 243 * 3,
-            spawn_mode: SpawnMode::MengerSponge,
-            survival: vec![4,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26].into(),
-            birth: vec![4, 18,19,20,21,22,23,24].into(),
-            states: 5,
-            neighbor_mode: NeighborMode::Moore,
-            color_mode: ColorMode::StateLerp(Color::rgb_u8(94, 25, 146), Color::rgb_u8(12, 227, 227)),
-        })
-        .add_plugin(ExtractResourcePlugin::<Rule>::default())
-        .add_system(update_materials);
+        app.init_resource::<Rule>()
+            .init_resource::<Presets>()
+            .add_plugin(ExtractResourcePlugin::<Rule>::default())
+            .add_system(update_materials);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::presets::builtin;
+
+    fn rule(survival: &str, birth: &str, neighbor_mode: NeighborMode, radius: u32) -> Rule {
+        Rule {
+            size: UVec3::splat(16),
+            survival: Value::try_parse(survival).unwrap(),
+            birth: Value::try_parse(birth).unwrap(),
+            states: 7,
+            neighbor_mode,
+            radius,
+            ..Rule::default()
+        }
+    }
+
+    fn assert_round_trip(rule: &Rule) {
+        let export = rule.export();
+        let imported = rule
+            .import(&export, RuleFormat::Auto)
+            .unwrap_or_else(|e| panic!("`{}` doesn't import: {}", export, e));
+        assert_eq!(imported.survival, rule.survival, "{}", export);
+        assert_eq!(imported.birth, rule.birth, "{}", export);
+        assert_eq!(imported.states, rule.states, "{}", export);
+        assert_eq!(imported.neighbor_mode, rule.neighbor_mode, "{}", export);
+        assert_eq!(imported.radius, rule.radius, "{}", export);
+        assert_eq!(imported.weights, rule.weights, "{}", export);
+    }
+
+    #[test]
+    fn builtin_presets_round_trip() {
+        for preset in builtin() {
+            assert_round_trip(&preset.rule);
+        }
+    }
+
+    #[test]
+    fn larger_radii_round_trip() {
+        assert_round_trip(&rule("0,5,100-124", "20-40", NeighborMode::Moore, 2));
+        assert_round_trip(&rule("1-3,342", "200", NeighborMode::Moore, 3));
+        assert_round_trip(&rule("6-24", "3", NeighborMode::VonNeumann, 3));
+    }
+
+    #[test]
+    fn custom_mask_round_trips() {
+        let mask = NeighborMode::VonNeumann.mask() | 1;
+        let custom = rule("2,3", "7", NeighborMode::Custom(mask), 1);
+        assert_eq!(custom.export(), "2,3/7/8/X020b411");
+        assert_round_trip(&custom);
+        assert_round_trip(&Rule {
+            neighbor_mode: NeighborMode::Custom(0),
+            ..rule("", "", NeighborMode::Moore, 1)
+        });
+    }
+
+    #[test]
+    fn shrunk_neighborhood_round_trips() {
+        let mut rule = rule("4,9-26", "4,18-24", NeighborMode::Moore, 1);
+        rule.neighbor_mode = NeighborMode::VonNeumann;
+        let max = rule.max_neighbors();
+        rule.survival.clear_above(max);
+        rule.birth.clear_above(max);
+        assert_eq!(rule.export(), "4/4/8/N");
+        assert_round_trip(&rule);
+    }
+
+    #[test]
+    fn custom_cells_are_left_out_of_files() {
+        let size = UVec3::splat(8);
+        let cells = CpuGrid::from_cells(size, vec![3; 8 * 8 * 8]);
+        let custom = Rule {
+            spawn_mode: SpawnMode::Custom(CustomCells::new(cells, 0)),
+            ..rule("4", "4", NeighborMode::Moore, 1)
+        };
+        let saved = custom.without_custom_cells();
+        assert_eq!(saved.spawn_mode, SpawnMode::MengerSponge);
+        assert_eq!(saved.export(), custom.export());
+        assert!(!ron::to_string(&saved).unwrap().contains("3,3"));
+    }
+
+    #[test]
+    fn weights_round_trip() {
+        let classes = Rule {
+            weights: NeighborWeights::Classes {
+                face: 2,
+                edge: 1,
+                corner: 0,
+            },
+            ..rule("10-24", "12", NeighborMode::Moore, 1)
+        };
+        assert_eq!(classes.export(), "10-24/12/8/M/W2,1,0");
+        assert_round_trip(&classes);
+
+        let mut offsets = [1; 26];
+        offsets[0] = 255;
+        offsets[25] = 0;
+        let offsets = Rule {
+            weights: NeighborWeights::Offsets(offsets),
+            ..rule(
+                "255-279",
+                "1",
+                NeighborMode::Custom(NeighborMode::FULL_MASK),
+                1,
+            )
+        };
+        assert_round_trip(&offsets);
     }
 }
@@ -0,0 +1,380 @@
+use std::{
+    fs,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    ca_compute::Generation,
+    cpu::CpuGrid,
+    parse::MAX_STATES,
+    presets::FileError,
+    readback::{GridReadback, RequestReadback},
+    rule::{CustomCells, Rule, SpawnMode, MAX_EXTENT},
+};
+
+/// Directory snapshots are saved to and loaded from, relative to the working directory.
+pub const SNAPSHOT_DIR: &str = "snapshots";
+const SNAPSHOT_EXTENSION: &str = "cas";
+
+const MAGIC: &[u8; 4] = b"CASS";
+/// Bumped whenever the layout below changes.
+const VERSION: u32 = 1;
+/// Longer rules are rejected rather than allocated, they can't be from this
+/// program and are more likely a corrupt header.
+const MAX_RULE_LEN: u32 = 1 << 16;
+
+pub struct SnapshotPlugin;
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SnapshotRequest>()
+            .add_system(save_snapshots);
+    }
+}
+
+/// The grid at some generation along with the rule it was evolved by.
+///
+/// Stored as, in little endian:
+/// - `MAGIC` and `VERSION`
+/// - the grid size as three u32s, the rule's state count as a u32 and the generation as a u64
+/// - the length of the rule as a u32, followed by the rule in RON
+/// - runs of equal cells in texture order, each the run length and then the
+///   state, both as LEB128
+pub struct Snapshot {
+    pub generation: u64,
+    pub rule: Rule,
+    pub grid: CpuGrid,
+}
+
+impl Snapshot {
+    pub fn write(&self, w: &mut impl Write) -> Result<(), FileError> {
+        // The snapshot's cells take the place of custom ones, don't store them twice.
+        let rule = ron::to_string(&self.rule.without_custom_cells())?;
+
+        let size = self.grid.size();
+        w.write_all(MAGIC)?;
+        for v in [VERSION, size.x, size.y, size.z, self.rule.states] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        w.write_all(&self.generation.to_le_bytes())?;
+        w.write_all(&(rule.len() as u32).to_le_bytes())?;
+        w.write_all(rule.as_bytes())?;
+
+        let mut cells = self.grid.cells().iter().peekable();
+        while let Some(&state) = cells.next() {
+            let mut run = 1;
+            while cells.next_if_eq(&&state).is_some() {
+                run += 1;
+            }
+            write_varint(w, run)?;
+            write_varint(w, state)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(r: &mut impl Read) -> Result<Self, FileError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(FileError::Invalid("missing header"));
+        }
+        let version = read_u32(r)?;
+        if version != VERSION {
+            return Err(FileError::Version {
+                found: version,
+                expected: VERSION,
+            });
+        }
+        let size = UVec3::new(read_u32(r)?, read_u32(r)?, read_u32(r)?);
+        if size.min_element() == 0 || size.max_element() > MAX_EXTENT {
+            return Err(FileError::Invalid("grid size is out of range"));
+        }
+        let states = read_u32(r)?;
+        if !(1..=MAX_STATES).contains(&states) {
+            return Err(FileError::Invalid("state count is out of range"));
+        }
+        let mut generation = [0; 8];
+        r.read_exact(&mut generation)?;
+        let generation = u64::from_le_bytes(generation);
+
+        let rule_len = read_u32(r)?;
+        if rule_len > MAX_RULE_LEN {
+            return Err(FileError::Invalid("rule is too long"));
+        }
+        let mut rule = vec![0; rule_len as usize];
+        r.read_exact(&mut rule)?;
+        let rule =
+            std::str::from_utf8(&rule).map_err(|_| FileError::Invalid("rule is not UTF-8"))?;
+        let mut rule: Rule = ron::from_str(rule)?;
+        rule.size = size;
+        rule.states = states;
+
+        let len = size.x as u64 * size.y as u64 * size.z as u64;
+        // Grown run by run rather than reserved up front, so a corrupt header
+        // can't allocate gigabytes before the cells turn out to be missing.
+        let mut cells = Vec::new();
+        while (cells.len() as u64) < len {
+            let run = read_varint(r)?;
+            let state = read_varint(r)?;
+            if run == 0 || cells.len() as u64 + run as u64 > len {
+                return Err(FileError::Invalid("cells don't fit the grid"));
+            }
+            cells.resize(cells.len() + run as usize, state);
+        }
+
+        Ok(Self {
+            generation,
+            rule,
+            grid: CpuGrid::from_cells(size, cells),
+        })
+    }
+
+    /// Replaces `rule` with the snapshot's, spawning its cells on Reset and
+    /// continuing from its generation.
+    pub fn apply(self, rule: &mut Rule) {
+        *rule = self.rule;
+        rule.spawn_mode = SpawnMode::Custom(CustomCells::new(self.grid, self.generation));
+    }
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_varint(w: &mut impl Write, mut v: u32) -> io::Result<()> {
+    while v >= 0x80 {
+        w.write_all(&[v as u8 | 0x80])?;
+        v >>= 7;
+    }
+    w.write_all(&[v as u8])
+}
+
+fn read_varint(r: &mut impl Read) -> Result<u32, FileError> {
+    let mut v = 0u32;
+    for shift in (0..32).step_by(7) {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+        // Only the low 4 bits of the fifth byte fit in a `u32`.
+        if shift == 28 && byte[0] & 0xf0 != 0 {
+            return Err(FileError::Invalid("number is too large"));
+        }
+        v |= ((byte[0] & 0x7f) as u32) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    unreachable!("the fifth byte has no continuation bit")
+}
+
+/// Path of the snapshot file called `name`.
+pub fn snapshot_path(name: &str) -> PathBuf {
+    Path::new(SNAPSHOT_DIR).join(format!("{}.{}", name, SNAPSHOT_EXTENSION))
+}
+
+pub fn save_snapshot(snapshot: &Snapshot, path: impl AsRef<Path>) -> Result<(), FileError> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut w = BufWriter::new(fs::File::create(path)?);
+    snapshot.write(&mut w)?;
+    w.flush()?;
+    Ok(())
+}
+
+pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Snapshot, FileError> {
+    Snapshot::read(&mut BufReader::new(fs::File::open(path)?))
+}
+
+/// A snapshot waiting for the grid to be read back before it can be saved.
+#[derive(Resource, Default)]
+pub struct SnapshotRequest {
+    /// Where to save it and the generation it was asked for at.
+    pub save: Option<(PathBuf, u64)>,
+    /// How the last save went, taken by the GUI.
+    pub status: Option<String>,
+}
+
+impl SnapshotRequest {
+    pub fn request(&mut self, path: PathBuf, generation: &Generation) {
+        self.save = Some((path, generation.get()));
+    }
+}
+
+fn save_snapshots(
+    mut request: ResMut<SnapshotRequest>,
+    mut request_readback: ResMut<RequestReadback>,
+    mut readbacks: EventReader<GridReadback>,
+    rule: Res<Rule>,
+) {
+    let Some((path, generation)) = &request.save else {
+        readbacks.clear();
+        return;
+    };
+    let Some(readback) = readbacks
+        .iter()
+        .filter(|readback| readback.generation >= *generation)
+        .last()
+    else {
+        request_readback.0 = true;
+        return;
+    };
+    let snapshot = Snapshot {
+        generation: readback.generation,
+        rule: rule.clone(),
+        grid: readback.grid.to_cpu_grid(),
+    };
+    let status = match save_snapshot(&snapshot, path) {
+        Ok(()) => format!(
+            "Saved generation {} to {}",
+            snapshot.generation,
+            path.display()
+        ),
+        Err(e) => format!("Failed to save snapshot: {}", e),
+    };
+    request.save = None;
+    request.status = Some(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Color;
+
+    use super::*;
+    use crate::rule::{BoundaryMode, ColorMode, NeighborMode};
+
+    fn snapshot() -> Snapshot {
+        let size = UVec3::new(4, 3, 2);
+        let cells = (0..24).map(|i| if i < 10 { 0 } else { i % 5 }).collect();
+        Snapshot {
+            generation: 1234,
+            rule: Rule {
+                size,
+                spawn_mode: SpawnMode::Random(0.7, 99),
+                survival: vec![4, 5].into(),
+                birth: vec![4].into(),
+                states: 4,
+                neighbor_mode: NeighborMode::VonNeumann,
+                radius: 2,
+                boundary_mode: BoundaryMode::Wrap,
+                color_mode: ColorMode::Single(Color::WHITE),
+                ..Rule::default()
+            },
+            grid: CpuGrid::from_cells(size, cells),
+        }
+    }
+
+    fn bytes(snapshot: &Snapshot) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn read(bytes: &[u8]) -> Result<Snapshot, FileError> {
+        Snapshot::read(&mut &bytes[..])
+    }
+
+    /// Overwrites the little endian u32 at `offset`.
+    fn patched(mut bytes: Vec<u8>, offset: usize, value: u32) -> Vec<u8> {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let snapshot = snapshot();
+        let read = read(&bytes(&snapshot)).unwrap();
+        assert_eq!(read.generation, snapshot.generation);
+        assert_eq!(read.rule, snapshot.rule);
+        assert_eq!(read.grid, snapshot.grid);
+    }
+
+    #[test]
+    fn applies_cells_at_the_saved_generation() {
+        let snapshot = snapshot();
+        let grid = snapshot.grid.clone();
+        let mut rule = Rule {
+            states: 1,
+            ..snapshot.rule.clone()
+        };
+        snapshot.apply(&mut rule);
+        assert_eq!(rule.states, 4);
+        let SpawnMode::Custom(cells) = rule.spawn_mode else {
+            panic!("expected custom cells, got {:?}", rule.spawn_mode);
+        };
+        assert_eq!(*cells.grid, grid);
+        assert_eq!(cells.generation, 1234);
+    }
+
+    #[test]
+    fn rejects_other_files_and_versions() {
+        assert!(matches!(read(b"RIFF...."), Err(FileError::Invalid(_))));
+        let newer = patched(bytes(&snapshot()), 4, VERSION + 1);
+        assert!(matches!(
+            read(&newer),
+            Err(FileError::Version { found, expected: VERSION }) if found == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_out_of_range_headers() {
+        let bytes = bytes(&snapshot());
+        // Size at 8, 12 and 16, states at 20, generation at 24 and the rule length at 32.
+        for (offset, value) in [
+            (8, 0),
+            (16, 0),
+            (12, MAX_EXTENT + 1),
+            (20, 0),
+            (20, MAX_STATES + 1),
+            (32, u32::MAX),
+        ] {
+            let result = read(&patched(bytes.clone(), offset, value));
+            assert!(
+                matches!(result, Err(FileError::Invalid(_))),
+                "{} at {} was accepted",
+                value,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_cells() {
+        let bytes = bytes(&snapshot());
+        assert!(matches!(
+            read(&bytes[..bytes.len() - 1]),
+            Err(FileError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn large_grids_with_missing_cells_are_errors() {
+        let mut bytes = bytes(&snapshot());
+        let rule_len = u32::from_le_bytes(bytes[32..36].try_into().unwrap()) as usize;
+        bytes.truncate(36 + rule_len);
+        for offset in [8, 12, 16] {
+            bytes = patched(bytes, offset, MAX_EXTENT);
+        }
+        assert!(matches!(read(&bytes), Err(FileError::Io(_))));
+    }
+
+    #[test]
+    fn varints_must_fit_in_32_bits() {
+        for v in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u32::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, v).unwrap();
+            assert_eq!(read_varint(&mut &bytes[..]).unwrap(), v);
+        }
+        // u32::MAX with bit 32 set as well.
+        let bytes = [0xff, 0xff, 0xff, 0xff, 0x1f];
+        assert!(matches!(
+            read_varint(&mut &bytes[..]),
+            Err(FileError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+
+use crate::{
+    ca_compute::Generation,
+    readback::{GridReadback, PackedGrid, RequestReadback},
+    rule::{Rule, SpawnMode},
+};
+
+/// Counts cells in the grids read back from the GPU.
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Statistics>()
+            .init_resource::<StatsRecorder>()
+            .add_system(collect_stats)
+            .add_system(record_stats.after(collect_stats));
+    }
+}
+
+/// Samples kept for the graphs, the oldest are dropped first.
+pub const MAX_SAMPLES: usize = 1000;
+/// Rules with more states than this only get alive and decaying counts.
+pub const MAX_TRACKED_STATES: u32 = 1024;
+
+#[derive(Clone, Debug)]
+pub struct GenerationStats {
+    pub generation: u64,
+    /// Cells in the alive state.
+    pub alive: u64,
+    /// Cells decaying towards dead.
+    pub decaying: u64,
+    /// Cells born since the previous generation, if it was sampled too.
+    pub births: Option<u64>,
+    /// Cells that stopped being alive since the previous generation, if it
+    /// was sampled too.
+    pub deaths: Option<u64>,
+    /// Cells in each state, indexed by state. Empty for rules with more than
+    /// `MAX_TRACKED_STATES`.
+    pub states: Vec<u64>,
+    /// The smallest and largest position of any alive cell, if there are any.
+    pub bounds: Option<(UVec3, UVec3)>,
+    /// Hash of every cell, equal for equal grids.
+    pub hash: u64,
+}
+
+impl GenerationStats {
+    /// Counts `grid`, which is at `generation`. Births and deaths need
+    /// `previous` to be the grid at the generation before.
+    pub fn count(
+        generation: u64,
+        grid: &PackedGrid,
+        previous: Option<&PackedGrid>,
+        rule: &Rule,
+    ) -> Self {
+        let mut stats = Self {
+            generation,
+            alive: 0,
+            decaying: 0,
+            births: None,
+            deaths: None,
+            states: if rule.states <= MAX_TRACKED_STATES {
+                vec![0; rule.states as usize + 1]
+            } else {
+                Vec::new()
+            },
+            bounds: None,
+            hash: {
+                let mut hasher = DefaultHasher::new();
+                grid.bytes().hash(&mut hasher);
+                hasher.finish()
+            },
+        };
+        let size = grid.size();
+        let (mut births, mut deaths) = (0, 0);
+        let mut previous_cells = previous.map(|previous| previous.cells());
+        for (i, cell) in grid.cells().enumerate() {
+            let alive = cell >= rule.states;
+            if alive {
+                stats.alive += 1;
+                let x = i as u32 % size.x;
+                let y = i as u32 / size.x % size.y;
+                let z = (i / (size.x as usize * size.y as usize)) as u32;
+                let pos = UVec3::new(x, y, z);
+                stats.bounds = Some(match stats.bounds {
+                    Some((min, max)) => (min.min(pos), max.max(pos)),
+                    None => (pos, pos),
+                });
+            } else if cell > 0 {
+                stats.decaying += 1;
+            }
+            if let Some(count) = stats.states.get_mut(cell as usize) {
+                *count += 1;
+            }
+            if let Some(was) = previous_cells.as_mut().and_then(|cells| cells.next()) {
+                if alive && was == 0 {
+                    births += 1;
+                } else if !alive && was >= rule.states {
+                    deaths += 1;
+                }
+            }
+        }
+        if previous.is_some() {
+            stats.births = Some(births);
+            stats.deaths = Some(deaths);
+        }
+        stats
+    }
+}
+
+/// Statistics of the latest generations, collected while `enabled`.
+#[derive(Resource, Default)]
+pub struct Statistics {
+    /// Off by default, since every sample copies the whole grid back to the CPU.
+    pub enabled: bool,
+    /// Sampled generations, oldest first. Generations can be skipped when
+    /// several are computed in a frame, or while the previous one is counted.
+    pub samples: VecDeque<GenerationStats>,
+    previous: Option<(u64, Arc<PackedGrid>)>,
+    counting: Option<Counting>,
+    /// The generation a readback was last requested at.
+    requested: Option<u64>,
+}
+
+/// A readback being counted on the `AsyncComputeTaskPool`, since counting a
+/// large grid takes far longer than a frame.
+struct Counting {
+    generation: u64,
+    grid: Arc<PackedGrid>,
+    /// Set by the task once it's done.
+    result: Arc<Mutex<Option<GenerationStats>>>,
+}
+
+impl Statistics {
+    pub fn latest(&self) -> Option<&GenerationStats> {
+        self.samples.back()
+    }
+
+    fn push(&mut self, sample: GenerationStats) {
+        // Going back, by a reset or a rewind, starts a new timeline.
+        self.samples
+            .retain(|kept| kept.generation < sample.generation);
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Starts counting `readback` off the main thread.
+    fn count(&mut self, readback: &GridReadback, rule: &Rule) {
+        let previous = self
+            .previous
+            .as_ref()
+            .filter(|(generation, grid)| {
+                *generation + 1 == readback.generation
+                    && grid.size() == readback.grid.size()
+                    && grid.format() == readback.grid.format()
+            })
+            .map(|(_, grid)| grid.clone());
+        let result = Arc::new(Mutex::new(None));
+        let (generation, grid, rule, done) = (
+            readback.generation,
+            readback.grid.clone(),
+            rule.clone(),
+            result.clone(),
+        );
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let sample = GenerationStats::count(generation, &grid, previous.as_deref(), &rule);
+                *done.lock().unwrap() = Some(sample);
+            })
+            .detach();
+        self.counting = Some(Counting {
+            generation,
+            grid: readback.grid.clone(),
+            result,
+        });
+    }
+}
+
+pub fn collect_stats(
+    mut stats: ResMut<Statistics>,
+    mut request: ResMut<RequestReadback>,
+    mut readbacks: EventReader<GridReadback>,
+    generation: Res<Generation>,
+    rule: Res<Rule>,
+) {
+    if !stats.enabled {
+        stats.previous = None;
+        stats.counting = None;
+        stats.requested = None;
+        readbacks.clear();
+        return;
+    }
+
+    let mut finished = false;
+    if let Some(counting) = &stats.counting {
+        let sample = counting.result.lock().unwrap().take();
+        if let Some(sample) = sample {
+            let counting = stats.counting.take().unwrap();
+            stats.push(sample);
+            stats.previous = Some((counting.generation, counting.grid));
+            finished = true;
+        }
+    }
+
+    // One grid is counted at a time, readbacks arriving in the meantime are
+    // skipped.
+    let latest = stats.latest().map(|latest| latest.generation);
+    let newest = readbacks
+        .iter()
+        .filter(|readback| Some(readback.generation) != latest)
+        .last();
+    let arrived = newest.is_some();
+    if stats.counting.is_none() {
+        if let Some(readback) = newest {
+            stats.count(readback, &rule);
+        }
+    }
+
+    // Only ask for generations that haven't been sampled, and only once the
+    // last one is counted, so a paused grid isn't copied back every frame and
+    // copies don't pile up while counting. A copy can be dropped while too
+    // many are in flight, or skipped while counting, so ask again once
+    // another arrives or a count finishes.
+    let current = generation.get();
+    let sampled = stats.latest().map(|latest| latest.generation) == Some(current);
+    if stats.counting.is_none()
+        && !sampled
+        && (stats.requested != Some(current) || arrived || finished)
+    {
+        request.0 = true;
+        stats.requested = Some(current);
+    }
+}
+
+/// Appends every sample to a CSV file while `path` is set, collecting
+/// statistics for as long as it is. Every run gets its own header block
+/// below the earlier ones, nothing in the file is overwritten.
+#[derive(Resource, Default)]
+pub struct StatsRecorder {
+    pub path: Option<PathBuf>,
+    /// How recording is going, for the GUI.
+    pub status: String,
+    file: Option<CsvFile>,
+}
+
+struct CsvFile {
+    path: PathBuf,
+    writer: BufWriter<fs::File>,
+    /// Whether the file holds anything yet, earlier runs included.
+    empty: bool,
+    /// The comment lines describing the current run.
+    header: String,
+    /// Per state columns of the current run, fixed by its header.
+    states: usize,
+    /// The last generation written, samples up to it aren't written again.
+    generation: Option<u64>,
+}
+
+impl CsvFile {
+    /// Opens the file for appending, keeping any earlier runs in it.
+    fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        Ok(Self {
+            empty: file.metadata()?.len() == 0,
+            path,
+            writer: BufWriter::new(file),
+            header: String::new(),
+            states: 0,
+            generation: None,
+        })
+    }
+
+    /// Describes the run of `rule`, a new run is started whenever this changes.
+    fn header(rule: &Rule) -> String {
+        let spawn = match &rule.spawn_mode {
+            SpawnMode::Random(_, seed) => format!("# seed: {}", seed),
+            SpawnMode::Custom(cells) => format!("# spawn: Custom, generation {}", cells.generation),
+            spawn_mode => format!("# spawn: {}", spawn_mode.kind().as_str()),
+        };
+        format!(
+            "# run\n# rule: {}\n{}\n# size: {}x{}x{}\n",
+            rule.export(),
+            spawn,
+            rule.size.x,
+            rule.size.y,
+            rule.size.z
+        )
+    }
+
+    /// Appends the header block of a new run, below any earlier ones.
+    fn start_run(&mut self, header: String, states: usize) -> io::Result<()> {
+        let writer = &mut self.writer;
+        if !self.empty {
+            writeln!(writer)?;
+        }
+        write!(writer, "{}", header)?;
+        write!(
+            writer,
+            "generation,population,decaying,births,deaths,min_x,min_y,min_z,max_x,max_y,max_z"
+        )?;
+        for state in 0..states {
+            write!(writer, ",state_{}", state)?;
+        }
+        writeln!(writer)?;
+        self.empty = false;
+        self.header = header;
+        self.states = states;
+        Ok(())
+    }
+
+    fn write(&mut self, sample: &GenerationStats) -> io::Result<()> {
+        let w = &mut self.writer;
+        let optional = |v: Option<u64>| v.map_or(String::new(), |v| v.to_string());
+        write!(
+            w,
+            "{},{},{},{},{}",
+            sample.generation,
+            sample.alive,
+            sample.decaying,
+            optional(sample.births),
+            optional(sample.deaths)
+        )?;
+        match sample.bounds {
+            Some((min, max)) => write!(
+                w,
+                ",{},{},{},{},{},{}",
+                min.x, min.y, min.z, max.x, max.y, max.z
+            )?,
+            None => write!(w, ",,,,,,")?,
+        }
+        for count in &sample.states {
+            write!(w, ",{}", count)?;
+        }
+        writeln!(w)?;
+        self.generation = Some(sample.generation);
+        Ok(())
+    }
+}
+
+fn record_stats(
+    mut recorder: ResMut<StatsRecorder>,
+    mut stats: ResMut<Statistics>,
+    rule: Res<Rule>,
+) {
+    let Some(path) = recorder.path.clone() else {
+        recorder.file = None;
+        return;
+    };
+    if !stats.enabled {
+        stats.enabled = true;
+    }
+    let Some(latest) = stats.latest() else {
+        return;
+    };
+    let recorder = &mut *recorder;
+
+    if recorder
+        .file
+        .as_ref()
+        .map_or(true, |file| file.path != path)
+    {
+        match CsvFile::open(path.clone()) {
+            Ok(file) => {
+                recorder.status = format!("Recording to {}", path.display());
+                recorder.file = Some(file);
+            }
+            Err(e) => {
+                recorder.status = format!("Failed to open {}: {}", path.display(), e);
+                recorder.path = None;
+                recorder.file = None;
+                return;
+            }
+        }
+    }
+    let Some(file) = &mut recorder.file else {
+        return;
+    };
+
+    // A new run whenever the automaton starts over, its rule or spawn
+    // changes or the columns do, so every run keeps its own header.
+    let header = CsvFile::header(&rule);
+    let went_back = file
+        .generation
+        .map_or(false, |generation| latest.generation < generation);
+    if went_back {
+        file.generation = None;
+    }
+    let restart = went_back || file.header != header || file.states != latest.states.len();
+    let result = if restart {
+        file.start_run(header, latest.states.len())
+    } else {
+        Ok(())
+    };
+    let written = file.generation;
+    let columns = file.states;
+    let result = result
+        .and_then(|()| {
+            stats
+                .samples
+                .iter()
+                .filter(|sample| {
+                    written.map_or(true, |generation| sample.generation > generation)
+                        && sample.states.len() == columns
+                })
+                .try_for_each(|sample| file.write(sample))
+        })
+        .and_then(|()| file.writer.flush());
+    if let Err(e) = result {
+        recorder.status = format!("Failed to write {}: {}", path.display(), e);
+        recorder.path = None;
+        recorder.file = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::CellFormat;
+
+    /// A 2x2x2 grid of `rule()` cells, in texture order.
+    fn grid(cells: [u8; 8], format: CellFormat) -> PackedGrid {
+        let mut bytes = Vec::new();
+        for cell in cells {
+            format.encode(cell as u32, &mut bytes);
+        }
+        PackedGrid::new(UVec3::splat(2), format, bytes)
+    }
+
+    fn rule() -> Rule {
+        Rule {
+            size: UVec3::splat(2),
+            states: 3,
+            ..Rule::default()
+        }
+    }
+
+    #[test]
+    fn counts_cells() {
+        let rule = rule();
+        let previous = grid([3, 3, 0, 0, 0, 0, 2, 0], CellFormat::R8);
+        let current = grid([0, 3, 2, 0, 0, 0, 1, 3], CellFormat::R8);
+
+        let stats = GenerationStats::count(8, &current, Some(&previous), &rule);
+        assert_eq!(stats.generation, 8);
+        assert_eq!((stats.alive, stats.decaying), (2, 2));
+        assert_eq!(stats.states, vec![4, 1, 1, 2]);
+        // Born at (1, 1, 1), while (0, 0, 0) died and (1, 0, 0) survived.
+        assert_eq!((stats.births, stats.deaths), (Some(1), Some(1)));
+        assert_eq!(
+            stats.bounds,
+            Some((UVec3::new(1, 0, 0), UVec3::new(1, 1, 1)))
+        );
+
+        let first = GenerationStats::count(0, &previous, None, &rule);
+        assert_eq!((first.births, first.deaths), (None, None));
+        assert_eq!(first.bounds, Some((UVec3::ZERO, UVec3::new(1, 0, 0))));
+    }
+
+    #[test]
+    fn counts_every_cell_format_alike() {
+        let rule = rule();
+        let cells = [0, 3, 2, 0, 0, 0, 1, 3];
+        let narrow = GenerationStats::count(1, &grid(cells, CellFormat::R8), None, &rule);
+        for format in [CellFormat::R16, CellFormat::R32] {
+            let wide = GenerationStats::count(1, &grid(cells, format), None, &rule);
+            assert_eq!(
+                (wide.alive, wide.decaying, &wide.states, wide.bounds),
+                (narrow.alive, narrow.decaying, &narrow.states, narrow.bounds)
+            );
+        }
+        let empty = GenerationStats::count(1, &grid([0; 8], CellFormat::R8), None, &rule);
+        assert_eq!((empty.alive, empty.bounds), (0, None));
+        assert_ne!(empty.hash, narrow.hash);
+    }
+
+    fn sample(generation: u64) -> GenerationStats {
+        GenerationStats {
+            generation,
+            alive: 1,
+            decaying: 0,
+            births: None,
+            deaths: None,
+            states: vec![0, 1],
+            bounds: None,
+            hash: 0,
+        }
+    }
+
+    #[test]
+    fn csv_runs_are_appended() {
+        let path = std::env::temp_dir().join(format!("cas_stats_{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        for run in 0..2 {
+            let mut file = CsvFile::open(path.clone()).unwrap();
+            file.start_run(format!("# run\n# seed: {}\n", run), 2)
+                .unwrap();
+            file.write(&sample(run)).unwrap();
+            file.writer.flush().unwrap();
+        }
+        let csv = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(csv.matches("# run\n").count(), 2);
+        assert!(csv.contains("# seed: 0\n") && csv.contains("# seed: 1\n"));
+        let rows: Vec<_> = csv
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.is_empty())
+            .collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].starts_with("generation,") && rows[2].starts_with("generation,"));
+        assert_eq!(rows[3], "1,1,0,,,,,,,,,0,1");
+    }
+}
@@ -0,0 +1,253 @@
+use bevy::prelude::*;
+
+use crate::{
+    ca_compute::Playback,
+    stats::{collect_stats, GenerationStats, Statistics},
+};
+
+/// Pauses the automaton once it's done evolving.
+pub struct StopPlugin;
+
+impl Plugin for StopPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StopConditions>()
+            .add_system(check_stop_conditions.after(collect_stats));
+    }
+}
+
+/// Conditions checked against the statistics of every sampled generation,
+/// collecting statistics while any is enabled. Since the grid is read back a
+/// few frames late, the automaton can be a few generations further along
+/// when it's paused.
+#[derive(Resource)]
+pub struct StopConditions {
+    /// Stop once no cell is alive.
+    pub extinction: bool,
+    /// Stop once the number of alive cells hasn't changed for `stable_generations`.
+    pub stable: bool,
+    pub stable_generations: u64,
+    /// Stop once the grid is the same as it was at most `max_period`
+    /// generations ago. The exact period is only known when every generation
+    /// in between was sampled, which several generations per frame prevent.
+    pub periodic: bool,
+    pub max_period: u64,
+    /// Why the automaton was stopped, if it was. Conditions aren't checked
+    /// again until the run starts over, so it can be resumed.
+    pub stopped: Option<String>,
+    checked: Option<u64>,
+}
+
+impl Default for StopConditions {
+    fn default() -> Self {
+        Self {
+            extinction: false,
+            stable: false,
+            stable_generations: 50,
+            periodic: false,
+            max_period: 20,
+            stopped: None,
+            checked: None,
+        }
+    }
+}
+
+impl StopConditions {
+    fn any(&self) -> bool {
+        self.extinction || self.stable || self.periodic
+    }
+
+    /// What `latest` meets, given the samples before it, oldest first.
+    fn check(&self, samples: &[&GenerationStats], latest: &GenerationStats) -> Option<String> {
+        let generation = latest.generation;
+        if self.extinction && latest.alive == 0 {
+            return Some(format!("Died out at generation {}", generation));
+        }
+        if self.periodic {
+            let repeat = samples
+                .iter()
+                .rposition(|sample| sample.hash == latest.hash)
+                .filter(|&i| generation - samples[i].generation <= self.max_period);
+            if let Some(i) = repeat {
+                let distance = generation - samples[i].generation;
+                // Sampled generations only increase, so there are as many
+                // samples since the repeat as generations only if none were
+                // skipped. Otherwise the period is some divisor of the distance.
+                let exact = (samples.len() - i) as u64 == distance;
+                return Some(if !exact {
+                    format!(
+                        "Repeating within {} generations, detected at generation {}",
+                        distance, generation
+                    )
+                } else if distance == 1 {
+                    format!("Still life detected at generation {}", generation)
+                } else {
+                    format!(
+                        "Oscillator with period {} detected at generation {}",
+                        distance, generation
+                    )
+                });
+            }
+        }
+        if self.stable && generation >= self.stable_generations {
+            // From the last sample at or before `since`, as generations can be skipped.
+            let since = generation - self.stable_generations;
+            let start = samples
+                .iter()
+                .rposition(|sample| sample.generation <= since);
+            if let Some(start) = start {
+                if samples[start..]
+                    .iter()
+                    .all(|sample| sample.alive == latest.alive)
+                {
+                    return Some(format!(
+                        "Population stable at {} since generation {}",
+                        latest.alive, since
+                    ));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn check_stop_conditions(
+    mut conditions: ResMut<StopConditions>,
+    mut stats: ResMut<Statistics>,
+    mut playback: ResMut<Playback>,
+) {
+    if !conditions.any() {
+        return;
+    }
+    if !stats.enabled {
+        stats.enabled = true;
+    }
+    let Some(latest) = stats.latest() else {
+        return;
+    };
+    if conditions.checked == Some(latest.generation) {
+        return;
+    }
+    // A reset or a rewind starts a new run.
+    if conditions
+        .checked
+        .map_or(false, |checked| latest.generation < checked)
+    {
+        conditions.stopped = None;
+    }
+    conditions.checked = Some(latest.generation);
+    if conditions.stopped.is_some() {
+        return;
+    }
+
+    let samples: Vec<_> = stats.samples.iter().take(stats.samples.len() - 1).collect();
+    if let Some(reason) = conditions.check(&samples, latest) {
+        info!("{}", reason);
+        conditions.stopped = Some(reason);
+        playback.paused = true;
+        playback.steps = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(generation: u64, alive: u64, hash: u64) -> GenerationStats {
+        GenerationStats {
+            generation,
+            alive,
+            decaying: 0,
+            births: None,
+            deaths: None,
+            states: Vec::new(),
+            bounds: None,
+            hash,
+        }
+    }
+
+    fn check(conditions: &StopConditions, samples: &[GenerationStats]) -> Option<String> {
+        let (latest, samples) = samples.split_last().unwrap();
+        conditions.check(&samples.iter().collect::<Vec<_>>(), latest)
+    }
+
+    #[test]
+    fn stops_on_extinction() {
+        let conditions = StopConditions {
+            extinction: true,
+            ..default()
+        };
+        assert_eq!(check(&conditions, &[sample(3, 1, 0)]), None);
+        assert_eq!(
+            check(&conditions, &[sample(3, 1, 0), sample(4, 0, 1)]).as_deref(),
+            Some("Died out at generation 4")
+        );
+    }
+
+    #[test]
+    fn stops_on_stable_population() {
+        let conditions = StopConditions {
+            stable: true,
+            stable_generations: 10,
+            ..default()
+        };
+        let stable: Vec<_> = (0..=10)
+            .map(|generation| sample(generation, 5, generation))
+            .collect();
+        assert_eq!(
+            check(&conditions, &stable).as_deref(),
+            Some("Population stable at 5 since generation 0")
+        );
+
+        let mut changed = stable.clone();
+        changed[3].alive = 6;
+        assert_eq!(check(&conditions, &changed), None);
+
+        // Skipped generations count as long as a sample covers the start.
+        let skipped = [
+            sample(0, 5, 0),
+            sample(8, 5, 1),
+            sample(16, 5, 2),
+            sample(20, 5, 3),
+        ];
+        assert_eq!(
+            check(&conditions, &skipped).as_deref(),
+            Some("Population stable at 5 since generation 10")
+        );
+        assert_eq!(check(&conditions, &skipped[2..]), None);
+    }
+
+    #[test]
+    fn detects_periods_only_when_every_generation_was_sampled() {
+        let conditions = StopConditions {
+            periodic: true,
+            max_period: 20,
+            ..default()
+        };
+        let oscillator = [
+            sample(1, 4, 1),
+            sample(2, 4, 2),
+            sample(3, 4, 3),
+            sample(4, 4, 1),
+        ];
+        assert_eq!(
+            check(&conditions, &oscillator).as_deref(),
+            Some("Oscillator with period 3 detected at generation 4")
+        );
+        assert_eq!(
+            check(&conditions, &[sample(7, 4, 9), sample(8, 4, 9)]).as_deref(),
+            Some("Still life detected at generation 8")
+        );
+
+        // Sampled every 8 generations, a still life could also have any period dividing 8.
+        let skipped = [sample(0, 4, 9), sample(8, 4, 9), sample(16, 4, 9)];
+        assert_eq!(
+            check(&conditions, &skipped).as_deref(),
+            Some("Repeating within 8 generations, detected at generation 16")
+        );
+
+        let too_long = [sample(0, 4, 1), sample(30, 4, 1)];
+        assert_eq!(check(&conditions, &too_long), None);
+        let changing = [sample(1, 4, 1), sample(2, 4, 2), sample(3, 4, 3)];
+        assert_eq!(check(&conditions, &changing), None);
+    }
+}